@@ -0,0 +1,79 @@
+#import bevy_pbr::{
+    mesh_view_bindings::view,
+    view_transformations::position_world_to_clip,
+}
+
+// Same as `Droplet` in `erosion.wgsl`.
+struct Droplet {
+    position: vec2f,
+    speed: vec2f,
+    volume: f32,
+    sediment: f32,
+    age: u32,
+    spawns: u32,
+    stroke: u32,
+};
+
+struct DropletParticleUniform {
+    color: vec4f,
+    size: f32,
+};
+
+@group(2) @binding(0) var<uniform> particles: DropletParticleUniform;
+@group(2) @binding(1) var heightmap_texture: texture_2d<f32>;
+@group(2) @binding(2) var<storage, read> droplets: array<Droplet>;
+
+const TERRAIN_SIZE = 256.0;
+// Height of the particles above the terrain, so they are not hidden by it.
+const PARTICLE_OFFSET = 0.3;
+
+struct Vertex {
+    // One instance per droplet.
+    @builtin(instance_index) droplet_index: u32,
+    // Corner of the quad, from -1 to 1 in `xy`.
+    @location(0) position: vec3f,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4f,
+    @location(0) corner: vec2f,
+    @location(1) opacity: f32,
+};
+
+// Each instance of the quad is a camera facing particle placed at its droplet.
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+    // The droplet buffer can lag behind a change of the droplet count.
+    if vertex.droplet_index >= arrayLength(&droplets) {
+        out.position = vec4f(0.0);
+        return out;
+    }
+    let drop = droplets[vertex.droplet_index];
+
+    let cell = vec2u(clamp(drop.position, vec2f(0.0), vec2f(TERRAIN_SIZE - 1.0)));
+    let height = textureLoad(heightmap_texture, cell, 0).r;
+    let center = vec3f(drop.position.x - TERRAIN_SIZE / 2.0, height + PARTICLE_OFFSET, drop.position.y - TERRAIN_SIZE / 2.0);
+
+    // Droplets shrink and fade as they evaporate, dead and unspawned ones have no volume.
+    let volume = clamp(drop.volume, 0.0, 1.0);
+    let radius = 0.5 * particles.size * sqrt(volume);
+    let right = view.world_from_view[0].xyz;
+    let up = view.world_from_view[1].xyz;
+    let world_position = center + radius * (vertex.position.x * right + vertex.position.y * up);
+
+    out.position = position_world_to_clip(world_position);
+    out.corner = vertex.position.xy;
+    out.opacity = particles.color.a * volume;
+    return out;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4f {
+    let distance = length(in.corner);
+    if distance > 1.0 {
+        discard;
+    }
+    let alpha = in.opacity * (1.0 - smoothstep(0.5, 1.0, distance));
+    return vec4f(particles.color.rgb, alpha);
+}
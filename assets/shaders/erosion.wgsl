@@ -5,6 +5,11 @@ struct Config {
     noise_seed: i32,
     noise_amplitude: f32,
     noise_base_frequency: f32,
+    noise_warp_levels: u32,
+    noise_warp_strength: f32,
+    noise_warp_frequency: f32,
+    sea_cutoff: u32,
+    sea_level: f32,
     time_seconds: f32,
     dt: f32,
     density: f32,
@@ -12,9 +17,46 @@ struct Config {
     deposition_rate: f32,
     min_volume: f32,
     friction: f32,
-    drops_per_frame_per_chunck: u32,
-    drop_count: u32,
-    max_drops: u32,
+    // Two (input, output) curve points per element.
+    curve_points: array<vec4f, 8>,
+    curve_point_count: u32,
+    curve_smooth: u32,
+    terrace_steps: u32,
+    terrace_sharpness: f32,
+    plateau_min: f32,
+    plateau_max: f32,
+    brush_center: vec2f,
+    brush_radius: f32,
+    brush_strength: f32,
+    brush_tool: u32,
+    brush_active: u32,
+    brush_target_height: f32,
+    brush_noise_frequency: f32,
+    brush_strokes: u32,
+    record_trajectories: u32,
+    droplet_steps_per_frame: u32,
+    max_droplet_age: u32,
+};
+
+// A drop carried over between frames. Same as in `droplet_particles.wgsl`.
+struct Droplet {
+    position: vec2f,
+    speed: vec2f,
+    // Dead, and respawned at its next step, at or below `config.min_volume`.
+    volume: f32,
+    sediment: f32,
+    // Steps taken since it spawned.
+    age: u32,
+    // Number of times it spawned, to seed its next spawn position.
+    spawns: u32,
+    // `config.brush_strokes` when it spawned.
+    stroke: u32,
+};
+
+// Heights encoded with `float_to_ordered`, so they can be compared with integer atomics.
+struct HeightRange {
+    min: atomic<u32>,
+    max: atomic<u32>,
 };
 
 @group(0) @binding(0) var<uniform> config: Config;
@@ -22,6 +64,44 @@ struct Config {
 @group(1) @binding(0) var heightmap: texture_storage_2d<r32float, read_write>;
 @group(1) @binding(1) var normalmap_topleft: texture_storage_2d<rgba32float, read_write>;
 @group(1) @binding(2) var normalmap_bottomright: texture_storage_2d<rgba32float, read_write>;
+@group(1) @binding(3) var mask: texture_storage_2d<r32float, read_write>;
+@group(1) @binding(4) var discharge: texture_storage_2d<r32float, read_write>;
+@group(1) @binding(5) var momentum: texture_storage_2d<rgba32float, read_write>;
+@group(1) @binding(6) var erosion_map: texture_storage_2d<r32float, read_write>;
+// `TRAJECTORY_LENGTH` steps of each recorded drop, as position, volume and sediment. A negative
+// volume ends a trajectory.
+@group(1) @binding(7) var<storage, read_write> trajectories: array<vec4f>;
+@group(1) @binding(8) var<storage, read_write> droplets: array<Droplet>;
+
+@group(2) @binding(0) var<storage, read_write> height_range: HeightRange;
+@group(2) @binding(1) var brush_heights: texture_storage_2d<r32float, read_write>;
+// Discharge, momentum x and momentum y of each cell for the current frame in fixed point, then the
+// number of drop steps taken in it.
+@group(2) @binding(2) var<storage, read_write> flow_track: array<atomic<i32>>;
+
+// Same as `BrushTool` in `brush.rs`.
+const BRUSH_RAISE = 1u;
+const BRUSH_LOWER = 2u;
+const BRUSH_SMOOTH = 3u;
+const BRUSH_FLATTEN = 4u;
+const BRUSH_NOISE = 5u;
+const BRUSH_RAIN = 6u;
+
+// Smooth and flatten blend towards their target by this fraction of the brush strength.
+const BRUSH_BLEND_RATE = 0.2;
+
+// The `update` kernel runs rows of invocations over the droplets, `DROPLET_ROW` is the same as in
+// `droplets.rs`.
+const DROPLET_ROW = 64u;
+
+// Same as in `trajectories.rs`.
+const TRAJECTORY_COUNT = 16u;
+const TRAJECTORY_LENGTH = 256u;
+
+// Fixed point scale of `flow_track`.
+const FLOW_FIXED_SCALE = 1024.0;
+// Fraction of the current frame blended into the discharge and momentum maps.
+const FLOW_BLEND_RATE = 0.02;
 
 fn mod289(x: vec2f) -> vec2f {
     return x - floor(x * (1. / 289.)) * 289.;
@@ -98,7 +178,32 @@ fn random_coord(value: u32) -> u32 {
     return hash(value + u32(config.time_seconds)) % (TERRAIN_SIZE * TERRAIN_SIZE);
 }
 
-fn sample_noise(location_f32: vec2f) -> f32 {
+fn warp_noise(location_f32: vec2f, shift: vec2f) -> f32 {
+    var result = 0.0;
+    for (var i: i32 = 0; i < 4; i++) {
+        let variable_scaling = pow(2.0, f32(i));
+        result += simplexNoise2(location_f32 * config.noise_warp_frequency * variable_scaling + shift + f32(config.noise_seed)) / variable_scaling;
+    }
+    return result;
+}
+
+// Domain warping, see: https://iquilezles.org/articles/warp
+// Every level offsets the sampling coordinates by noise evaluated at the previously warped coordinates.
+fn warp_location(location_f32: vec2f) -> vec2f {
+    var offset = vec2f(0.0);
+    for (var level: u32 = 0u; level < min(config.noise_warp_levels, 2u); level++) {
+        let shift = f32(level) * vec2f(1.7, 9.2);
+        let p = location_f32 + offset;
+        offset = config.noise_warp_strength * vec2f(
+            warp_noise(p, shift),
+            warp_noise(p, shift + vec2f(5.2, 1.3)),
+        );
+    }
+    return location_f32 + offset;
+}
+
+fn sample_noise(location: vec2f) -> f32 {
+    let location_f32 = warp_location(location);
     var result = 0.0;
     for (var i: i32 = 0; i < 6; i++) {
         let variable_scaling = pow(2.0, f32(i));
@@ -131,20 +236,42 @@ fn get_gradient(p: vec2i) -> vec2f {
     return vec2f((right - left) / 2.0, (up - down) / 2.0);
 }
 
+fn store_normals(location: vec2u, height: f32) {
+    let location_f32 = vec2f(location);
+
+    let a = vec3f(location_f32.x + 0.0, height,                             location_f32.y + 0.0);
+    let b = vec3f(location_f32.x + 1.0, get_height(location + vec2u(1, 0)), location_f32.y + 0.0);
+    let c = vec3f(location_f32.x + 0.0, get_height(location + vec2u(0, 1)), location_f32.y + 1.0);
+    let d = vec3f(location_f32.x + 1.0, get_height(location + vec2u(1, 1)), location_f32.y + 1.0);
+
+    let n1 = normalize(cross(a - b, c - b));
+    let n2 = normalize(cross(d - c, b - c));
+
+    textureStore(normalmap_topleft, location, vec4f(n1, 0.0));
+    textureStore(normalmap_bottomright, location, vec4f(n2, 0.0));
+}
+
 fn get_normal_from_gradient(p: vec2i) -> vec3f {
     let g = get_gradient(p);
     return normalize(vec3f(-g.x, 1.0, -g.y));
 }
 
+// Noise multiplied by the mask, with everything below sea level optionally raised to it.
+fn initial_height(location_i32: vec2i) -> f32 {
+    let mask_location = clamp(location_i32, vec2i(0), vec2i(i32(TERRAIN_SIZE) - 1));
+    let height = sample_noise(vec2f(location_i32)) * textureLoad(mask, mask_location).x;
+    return select(height, max(height, config.sea_level), config.sea_cutoff != 0u);
+}
+
 @compute @workgroup_size(8, 8, 1)
 fn init(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     let location_i32 = vec2i(i32(invocation_id.x), i32(invocation_id.y));
     let location_f32 = vec2f(f32(location_i32.x), f32(location_i32.y));
 
-    let a = vec3f(location_f32.x + 0.0, sample_noise(location_f32 + vec2f(0.0, 0.0)), location_f32.y + 0.0);
-    let b = vec3f(location_f32.x + 1.0, sample_noise(location_f32 + vec2f(1.0, 0.0)), location_f32.y + 0.0);
-    let c = vec3f(location_f32.x + 0.0, sample_noise(location_f32 + vec2f(0.0, 1.0)), location_f32.y + 1.0);
-    let d = vec3f(location_f32.x + 1.0, sample_noise(location_f32 + vec2f(1.0, 1.0)), location_f32.y + 1.0);
+    let a = vec3f(location_f32.x + 0.0, initial_height(location_i32 + vec2i(0, 0)), location_f32.y + 0.0);
+    let b = vec3f(location_f32.x + 1.0, initial_height(location_i32 + vec2i(1, 0)), location_f32.y + 0.0);
+    let c = vec3f(location_f32.x + 0.0, initial_height(location_i32 + vec2i(0, 1)), location_f32.y + 1.0);
+    let d = vec3f(location_f32.x + 1.0, initial_height(location_i32 + vec2i(1, 1)), location_f32.y + 1.0);
 
     let n1 = normalize(cross(a - b, c - b));
     let n2 = normalize(cross(d - c, b - c));
@@ -154,58 +281,271 @@ fn init(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
     textureStore(heightmap, location_i32, vec4f(a.y));
     textureStore(normalmap_topleft, location_i32, vec4f(n1, 0.0));
     textureStore(normalmap_bottomright, location_i32, vec4f(n2, 0.0));
+    textureStore(discharge, location_i32, vec4f(0.0));
+    textureStore(momentum, location_i32, vec4f(0.0));
+    textureStore(erosion_map, location_i32, vec4f(0.0));
 }
 
 
+fn random_unit(value: u32) -> f32 {
+    return f32(hash(value + u32(config.time_seconds))) / 4294967296.0;
+}
+
+// Uniformly distributed inside the brush while the rain brush is held, anywhere on the grid otherwise.
+fn spawn_position(seed: u32) -> vec2f {
+    if config.brush_tool == BRUSH_RAIN && config.brush_active != 0u {
+        let radius = config.brush_radius * sqrt(random_unit(seed));
+        let angle = 6.28318530718 * random_unit(seed ^ 0x9e3779b9u);
+        let position = config.brush_center + radius * vec2f(cos(angle), sin(angle));
+        return clamp(position, vec2f(0.0), vec2f(TERRAIN_SIZE_f32 - 1.0));
+    }
+
+    let rand_value = random_coord(seed);
+    return vec2f(vec2u(rand_value / TERRAIN_SIZE, rand_value % TERRAIN_SIZE));
+}
+
+fn spawn_droplet(index: u32, spawns: u32) -> Droplet {
+    let seed = hash(index) ^ hash(spawns + 0x632be5abu);
+    return Droplet(spawn_position(seed), vec2f(0.0), 1.0, 0.0, 0u, spawns + 1u, config.brush_strokes);
+}
+
+// Advances one droplet by `config.droplet_steps_per_frame` steps, respawning it as soon as it dies.
 @compute @workgroup_size(8, 8, 1)
 fn update(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
-    let rand_value = random_coord(invocation_id.x + invocation_id.y);
-    let newpos = vec2u(rand_value / TERRAIN_SIZE, rand_value % TERRAIN_SIZE);
-
     let dt = config.dt;
-
-    var drop_pos = vec2f(newpos);
-    var drop_speed = vec2f(0.0);
-    var drop_volume = 1.0;
-    var drop_sediment = 0.0;
-    var i = 0;
+    let index = invocation_id.x + invocation_id.y * DROPLET_ROW;
 
     storageBarrier();
 
-    while (drop_volume > config.min_volume && i < 1500) {
-        let prev_pos = vec2u(drop_pos);
-        let prev_pos_f32 = vec2f(prev_pos);
-        let normal = get_normal(drop_pos);
+    // The last row of invocations can go past the droplets, whose count is a setting.
+    if index >= arrayLength(&droplets) {
+        return;
+    }
+    var drop = droplets[index];
+
+    // The first droplets are recorded, when enabled.
+    let recorded = config.record_trajectories != 0u && index < TRAJECTORY_COUNT;
+    let trajectory = index * TRAJECTORY_LENGTH;
 
-        i += 1;
-        drop_speed += dt * vec2f(normal.x, normal.z) / (drop_volume * config.density);
-        drop_pos += dt * drop_speed;
-        drop_speed *= 1.0 - dt * config.friction;
+    // A new rain stroke replaces all droplets by ones inside the brush.
+    let raining = config.brush_tool == BRUSH_RAIN;
 
-        if drop_pos.x < 0.0 || drop_pos.y < 0.0 || drop_pos.x >= TERRAIN_SIZE_f32 || drop_pos.y >= TERRAIN_SIZE_f32 {
-            break;
+    for (var step = 0u; step < config.droplet_steps_per_frame; step++) {
+        let stale = raining && drop.stroke != config.brush_strokes;
+        if stale || drop.volume <= config.min_volume || drop.age >= config.max_droplet_age {
+            drop = spawn_droplet(index, drop.spawns);
         }
 
-        let max_sediment = drop_volume * length(drop_speed) * (get_height(prev_pos) - get_height(vec2u(drop_pos)));
-        let sediment_diff = max(0.0, max_sediment) - drop_sediment;
-        let erosion = dt * drop_volume * config.deposition_rate * sediment_diff;
+        if recorded && drop.age + 1u < TRAJECTORY_LENGTH {
+            trajectories[trajectory + drop.age] = vec4f(drop.position, drop.volume, drop.sediment);
+            trajectories[trajectory + drop.age + 1u] = vec4f(drop.position, -1.0, drop.sediment);
+        }
+
+        let prev_pos = vec2u(drop.position);
+        let normal = get_normal(drop.position);
 
-        drop_sediment += dt * config.deposition_rate * sediment_diff;
-        drop_volume *= 1.0 - dt * config.evap_rate;
+        drop.age += 1u;
+        drop.speed += dt * vec2f(normal.x, normal.z) / (drop.volume * config.density);
+        drop.position += dt * drop.speed;
+        drop.speed *= 1.0 - dt * config.friction;
+
+        if drop.position.x < 0.0 || drop.position.y < 0.0 || drop.position.x >= TERRAIN_SIZE_f32 || drop.position.y >= TERRAIN_SIZE_f32 {
+            drop.volume = 0.0;
+            continue;
+        }
+
+        let max_sediment = drop.volume * length(drop.speed) * (get_height(prev_pos) - get_height(vec2u(drop.position)));
+        let sediment_diff = max(0.0, max_sediment) - drop.sediment;
+        let erosion = dt * drop.volume * config.deposition_rate * sediment_diff;
+
+        drop.sediment += dt * config.deposition_rate * sediment_diff;
+        drop.volume *= 1.0 - dt * config.evap_rate;
 
         let height = get_height(prev_pos);
         let new_height = height - erosion;
 
-        let a = vec3f(prev_pos_f32.x + 0.0, new_height,                         prev_pos_f32.y + 0.0);
-        let b = vec3f(prev_pos_f32.x + 1.0, get_height(prev_pos + vec2u(1, 0)), prev_pos_f32.y + 0.0);
-        let c = vec3f(prev_pos_f32.x + 0.0, get_height(prev_pos + vec2u(0, 1)), prev_pos_f32.y + 1.0);
-        let d = vec3f(prev_pos_f32.x + 1.0, get_height(prev_pos + vec2u(1, 1)), prev_pos_f32.y + 1.0);
+        textureStore(heightmap, prev_pos, vec4f(new_height));
+        store_normals(prev_pos, new_height);
+        track_flow(prev_pos, drop.volume, drop.speed);
+        textureStore(erosion_map, prev_pos, textureLoad(erosion_map, prev_pos) + erosion);
+    }
 
-        let n1 = normalize(cross(a - b, c - b));
-        let n2 = normalize(cross(d - c, b - c));
+    droplets[index] = drop;
+}
 
-        textureStore(heightmap, prev_pos, vec4f(new_height));
-        textureStore(normalmap_topleft, prev_pos, vec4f(n1, 0.0));
-        textureStore(normalmap_bottomright, prev_pos, vec4f(n2, 0.0));
+fn track_flow(location: vec2u, volume: f32, speed: vec2f) {
+    let index = 4u * (location.x + location.y * TERRAIN_SIZE);
+    let momentum = vec2i(volume * speed * FLOW_FIXED_SCALE);
+    atomicAdd(&flow_track[index], i32(volume * FLOW_FIXED_SCALE));
+    atomicAdd(&flow_track[index + 1u], momentum.x);
+    atomicAdd(&flow_track[index + 2u], momentum.y);
+    atomicAdd(&flow_track[index + 3u], 1);
+}
+
+// Blends the flow tracked by the drops of this frame into the discharge and momentum maps, and
+// clears it for the next frame. The drop density is kept in the unused `z` of the momentum map.
+@compute @workgroup_size(8, 8, 1)
+fn flow_maps(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let location = invocation_id.xy;
+    let index = 4u * (location.x + location.y * TERRAIN_SIZE);
+    let frame_discharge = f32(atomicExchange(&flow_track[index], 0)) / FLOW_FIXED_SCALE;
+    let frame_momentum = vec2f(
+        f32(atomicExchange(&flow_track[index + 1u], 0)),
+        f32(atomicExchange(&flow_track[index + 2u], 0)),
+    ) / FLOW_FIXED_SCALE;
+    let frame_density = f32(atomicExchange(&flow_track[index + 3u], 0));
+
+    let new_discharge = mix(textureLoad(discharge, location).x, frame_discharge, FLOW_BLEND_RATE);
+    let new_momentum = mix(textureLoad(momentum, location).xyz, vec3f(frame_momentum, frame_density), FLOW_BLEND_RATE);
+    textureStore(discharge, location, vec4f(new_discharge));
+    textureStore(momentum, location, vec4f(new_momentum, 0.0));
+}
+
+// Recomputes both triangle normals of a cell, e.g. after heights were uploaded from the CPU.
+@compute @workgroup_size(8, 8, 1)
+fn normals(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let location = invocation_id.xy;
+    store_normals(location, get_height(location));
+}
+
+fn float_to_ordered(value: f32) -> u32 {
+    let bits = bitcast<u32>(value);
+    return select(bits | 0x80000000u, ~bits, (bits & 0x80000000u) != 0u);
+}
+
+fn ordered_to_float(value: u32) -> f32 {
+    return bitcast<f32>(select(~value, value & 0x7fffffffu, (value & 0x80000000u) != 0u));
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn find_height_range(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let height = float_to_ordered(get_height(invocation_id.xy));
+    atomicMin(&height_range.min, height);
+    atomicMax(&height_range.max, height);
+}
+
+fn curve_point(i: u32) -> vec2f {
+    let pair = config.curve_points[i / 2u];
+    return select(pair.xy, pair.zw, i % 2u == 1u);
+}
+
+fn curve_tangent(i: u32) -> f32 {
+    let previous = curve_point(max(i, 1u) - 1u);
+    let next = curve_point(min(i + 1u, config.curve_point_count - 1u));
+    return (next.y - previous.y) / max(next.x - previous.x, 1e-6);
+}
+
+fn hermite(y0: f32, y1: f32, m0: f32, m1: f32, f: f32) -> f32 {
+    let f2 = f * f;
+    let f3 = f2 * f;
+    return (2.0 * f3 - 3.0 * f2 + 1.0) * y0 + (f3 - 2.0 * f2 + f) * m0 + (-2.0 * f3 + 3.0 * f2) * y1 + (f3 - f2) * m1;
+}
+
+// Same as `HeightCurve::sample`.
+fn sample_curve(t: f32) -> f32 {
+    let count = config.curve_point_count;
+    if count == 0u {
+        return t;
+    }
+    if t <= curve_point(0u).x {
+        return curve_point(0u).y;
+    }
+
+    for (var i = 1u; i < count; i++) {
+        let p0 = curve_point(i - 1u);
+        let p1 = curve_point(i);
+        if t <= p1.x {
+            let width = max(p1.x - p0.x, 1e-6);
+            let f = (t - p0.x) / width;
+            if config.curve_smooth == 0u {
+                return mix(p0.y, p1.y, f);
+            }
+            return hermite(p0.y, p1.y, curve_tangent(i - 1u) * width, curve_tangent(i) * width, f);
+        }
+    }
+    return curve_point(count - 1u).y;
+}
+
+// Same as `terrace` in `operators.rs`.
+fn terrace(t: f32) -> f32 {
+    if config.terrace_steps == 0u {
+        return t;
     }
+
+    let steps = f32(config.terrace_steps);
+    let scaled = t * steps;
+    let base = floor(scaled);
+    let exponent = 1.0 / (1.0 - clamp(config.terrace_sharpness, 0.0, 0.99));
+    return (base + pow(scaled - base, exponent)) / steps;
+}
+
+// Applies the height curve, terraces and plateaus, relative to the range found by `find_height_range`.
+@compute @workgroup_size(8, 8, 1)
+fn operators(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let location = invocation_id.xy;
+    let min_height = ordered_to_float(atomicLoad(&height_range.min));
+    let max_height = ordered_to_float(atomicLoad(&height_range.max));
+    let range = max(max_height - min_height, 1e-6);
+
+    var t = (get_height(location) - min_height) / range;
+    t = sample_curve(t);
+    t = terrace(t);
+    t = clamp(t, config.plateau_min, config.plateau_max);
+
+    textureStore(heightmap, location, vec4f(min_height + t * range));
+}
+
+// From 1 at the center of the brush to 0 at its radius.
+fn brush_weight(location: vec2u) -> f32 {
+    let distance = length(vec2f(location) - config.brush_center) / max(config.brush_radius, 1e-6);
+    return 1.0 - smoothstep(0.0, 1.0, distance);
+}
+
+fn average_height(location: vec2u) -> f32 {
+    let max_location = vec2i(i32(TERRAIN_SIZE) - 1);
+    var sum = 0.0;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            sum += get_height_i(clamp(vec2i(location) + vec2i(x, y), vec2i(0), max_location));
+        }
+    }
+    return sum / 9.0;
+}
+
+// Writes the brushed heights into `brush_heights`, copied back by `apply_brush`.
+@compute @workgroup_size(8, 8, 1)
+fn brush(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let location = invocation_id.xy;
+    let height = get_height(location);
+    let amount = config.brush_strength * brush_weight(location);
+    let blend = saturate(amount * BRUSH_BLEND_RATE);
+
+    var new_height = height;
+    switch config.brush_tool {
+        case BRUSH_RAISE: {
+            new_height = height + amount;
+        }
+        case BRUSH_LOWER: {
+            new_height = height - amount;
+        }
+        case BRUSH_SMOOTH: {
+            new_height = mix(height, average_height(location), blend);
+        }
+        case BRUSH_FLATTEN: {
+            new_height = mix(height, config.brush_target_height, blend);
+        }
+        case BRUSH_NOISE: {
+            let noise = simplexNoise2(vec2f(location) * config.brush_noise_frequency + f32(config.noise_seed));
+            new_height = height + amount * noise;
+        }
+        default: {}
+    }
+
+    textureStore(brush_heights, location, vec4f(new_height));
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn apply_brush(@builtin(global_invocation_id) invocation_id: vec3<u32>) {
+    let location = invocation_id.xy;
+    textureStore(heightmap, location, textureLoad(brush_heights, location));
 }
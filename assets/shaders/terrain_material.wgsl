@@ -1,9 +1,11 @@
 #import bevy_pbr::{
     pbr_fragment::pbr_input_from_standard_material,
     pbr_functions::alpha_discard,
+    pbr_types::STANDARD_MATERIAL_FLAGS_UNLIT_BIT,
     view_transformations::position_world_to_clip,
 }
 #import bevy_pbr::mesh_functions::{get_world_from_local, mesh_position_local_to_world, mesh_normal_local_to_world}
+#import bevy_pbr::mesh_view_bindings::globals
 #ifdef PREPASS_PIPELINE
 #import bevy_pbr::{
     prepass_io::{VertexOutput, FragmentOutput},
@@ -19,7 +21,6 @@
 struct Vertex {
     @builtin(instance_index) instance_index: u32,
     @location(0) position: vec3<f32>,
-    @location(2) tex_coords: vec2<f32>,
 };
 
 @group(2) @binding(100) var heightmap_texture: texture_2d<f32>;
@@ -28,8 +29,132 @@ struct Vertex {
 @group(2) @binding(103) var normalmap_topleft_sampler: sampler;
 @group(2) @binding(104) var normalmap_bottomright_texture: texture_2d<f32>;
 @group(2) @binding(105) var normalmap_bottomright_sampler: sampler;
+@group(2) @binding(106) var discharge_texture: texture_2d<f32>;
+@group(2) @binding(107) var discharge_sampler: sampler;
+@group(2) @binding(108) var momentum_texture: texture_2d<f32>;
+@group(2) @binding(109) var momentum_sampler: sampler;
+
+// Same as `SplatRuleUniform` in `uniforms.rs`.
+struct SplatRule {
+    color: vec4<f32>,
+    altitude_slope: vec4<f32>,
+    moisture_erosion: vec4<f32>,
+    strength: f32,
+};
+
+// Same as `TerrainRenderUniform` in `uniforms.rs`.
+struct TerrainRenderSettings {
+    river_color: vec4<f32>,
+    river_threshold: f32,
+    river_flow_speed: f32,
+    show_rivers: u32,
+    smooth_shading: u32,
+    normal_mapping: u32,
+    splat_enabled: u32,
+    splat_blend: f32,
+    splat_texture_scale: f32,
+    contour_color: vec4<f32>,
+    contours: u32,
+    contour_minor_interval: f32,
+    contour_major_interval: f32,
+    hypsometric_tint: u32,
+    elevation_ramp: u32,
+    ramp_min: f32,
+    ramp_max: f32,
+    tint_strength: f32,
+    debug_view: u32,
+    debug_height_min: f32,
+    debug_height_max: f32,
+    debug_scale: f32,
+    debug_contour_interval: f32,
+    splat_rules: array<SplatRule, SPLAT_LAYER_COUNT>,
+};
+
+@group(2) @binding(110) var<uniform> settings: TerrainRenderSettings;
+@group(2) @binding(111) var erosion_texture: texture_2d<f32>;
+@group(2) @binding(112) var erosion_sampler: sampler;
+@group(2) @binding(113) var splat_textures: texture_2d_array<f32>;
+@group(2) @binding(114) var splat_sampler: sampler;
 
 const TERRAIN_SIZE = 256.0;
+const RIPPLE_FREQUENCY = 2.0;
+const RIPPLE_STRENGTH = 0.15;
+const RIVER_ROUGHNESS = 0.15;
+
+// Same as in `splat.rs`.
+const SPLAT_LAYER_COUNT = 5u;
+const ALTITUDE_SOFTNESS = 2.0;
+const SLOPE_SOFTNESS = 0.05;
+const MOISTURE_SOFTNESS = 0.1;
+const EROSION_SOFTNESS = 0.1;
+
+// Same as `ElevationRamp` in `rendering.rs`.
+const RAMP_ATLAS = 0u;
+const RAMP_SPECTRAL = 1u;
+
+// Opacity of the minor contour lines, relative to the major lines.
+const MINOR_CONTOUR_OPACITY = 0.5;
+
+// Same as `TerrainDebugView` in `rendering.rs`.
+const DEBUG_HEIGHT = 1u;
+const DEBUG_NORMALS = 2u;
+const DEBUG_SLOPE = 3u;
+const DEBUG_CURVATURE = 4u;
+const DEBUG_EROSION = 5u;
+const DEBUG_DROP_DENSITY = 6u;
+const DEBUG_DISCHARGE = 7u;
+const DEBUG_CONTOURS = 8u;
+
+// Normal of the triangle containing `position`, matching the triangulation of the full detail
+// chunk mesh. Vertices are shared between triangles, so this is looked up per fragment to keep
+// flat shading, which also keeps the shading detail of chunks with fewer vertices.
+fn triangle_normal(position: vec2f) -> vec3f {
+    let location = position + TERRAIN_SIZE / 2.0;
+    let cell = clamp(vec2i(floor(location)), vec2i(0), vec2i(i32(TERRAIN_SIZE) - 1));
+    let f = location - vec2f(cell);
+
+    if f.x + f.y < 1.0 {
+        return textureLoad(normalmap_topleft_texture, cell, 0).xyz;
+    }
+    return textureLoad(normalmap_bottomright_texture, cell, 0).xyz;
+}
+
+fn cell_height(cell: vec2i) -> f32 {
+    let clamped = clamp(cell, vec2i(0), vec2i(i32(TERRAIN_SIZE) - 1));
+    return textureLoad(heightmap_texture, clamped, 0).r;
+}
+
+// Same as `get_normal_from_gradient` in `erosion.wgsl`.
+fn cell_gradient_normal(cell: vec2i) -> vec3f {
+    let gradient = vec2f(
+        cell_height(cell + vec2i(1, 0)) - cell_height(cell - vec2i(1, 0)),
+        cell_height(cell + vec2i(0, 1)) - cell_height(cell - vec2i(0, 1)),
+    ) / 2.0;
+    return normalize(vec3f(-gradient.x, 1.0, -gradient.y));
+}
+
+// Smooth normal at `position`, interpolated between the gradient normals of the cells around it
+// like the heightmap itself is.
+fn gradient_normal(position: vec2f) -> vec3f {
+    let location = position + TERRAIN_SIZE / 2.0 - 0.5;
+    let cell = vec2i(floor(location));
+    let f = location - floor(location);
+
+    let top = mix(cell_gradient_normal(cell), cell_gradient_normal(cell + vec2i(1, 0)), f.x);
+    let bottom = mix(cell_gradient_normal(cell + vec2i(0, 1)), cell_gradient_normal(cell + vec2i(1, 1)), f.x);
+    return normalize(mix(top, bottom, f.y));
+}
+
+// Normal of the terrain surface at a fragment, following the shading mode.
+fn surface_normal(position: vec2f, interpolated_normal: vec3f) -> vec3f {
+    if settings.smooth_shading == 0u {
+        return triangle_normal(position);
+    }
+    if settings.normal_mapping != 0u {
+        return gradient_normal(position);
+    }
+    return normalize(interpolated_normal);
+}
 
 @vertex
 fn vertex(vertex: Vertex) -> VertexOutput {
@@ -37,62 +162,261 @@ fn vertex(vertex: Vertex) -> VertexOutput {
     var out: VertexOutput;
     let model = get_world_from_local(vertex.instance_index);
 
-    var pos = (vertex.position.xz + TERRAIN_SIZE / 2.0) / TERRAIN_SIZE;
-    var tex_coords = vec2f(pos);
+    // Chunks are placed by their transform, skirt vertices are offset below the surface.
+    out.world_position = mesh_position_local_to_world(model, vec4<f32>(vertex.position, 1.0));
+    let uv = (out.world_position.xz + TERRAIN_SIZE / 2.0) / TERRAIN_SIZE;
+    out.world_position.y += textureSampleLevel(heightmap_texture, heightmap_sampler, uv, 0.0).r;
 
-    if vertex.tex_coords.x == 1.0 {
-        tex_coords -= vec2f(0.0, 1.0) / TERRAIN_SIZE;
-    }
-    else if vertex.tex_coords.x == 2.0 {
-        tex_coords -= vec2f(1.0, 0.0) / TERRAIN_SIZE;
+    if settings.smooth_shading != 0u {
+        out.world_normal = gradient_normal(out.world_position.xz);
+    } else {
+        out.world_normal = triangle_normal(out.world_position.xz);
     }
-    else if vertex.tex_coords.x == 3.0 {
-        tex_coords -= vec2f(1.0, 1.0) / TERRAIN_SIZE;
+
+    out.position = position_world_to_clip(out.world_position.xyz);
+    out.instance_index = vertex.instance_index;
+    return out;
+}
+
+
+fn heightmap_uv(position: vec2f) -> vec2f {
+    return (position + TERRAIN_SIZE / 2.0) / TERRAIN_SIZE;
+}
+
+// How much of the surface is covered by a river, from the discharge map.
+fn river_amount(position: vec2f) -> f32 {
+    if settings.show_rivers == 0u {
+        return 0.0;
     }
-    else if vertex.tex_coords.x == 4.0 {
-        tex_coords -= vec2f(1.0, 0.0) / TERRAIN_SIZE;
+    let discharge = textureSampleLevel(discharge_texture, discharge_sampler, heightmap_uv(position), 0.0).r;
+    return smoothstep(settings.river_threshold, settings.river_threshold * 2.0, discharge);
+}
+
+// Tilts the normal with ripples travelling downstream, along the momentum map.
+fn river_normal(position: vec2f, normal: vec3f) -> vec3f {
+    let momentum = textureSampleLevel(momentum_texture, momentum_sampler, heightmap_uv(position), 0.0).xy;
+    if length(momentum) < 1e-6 {
+        return normal;
     }
-    else if vertex.tex_coords.x == 5.0 {
-        tex_coords -= vec2f(0.0, 1.0) / TERRAIN_SIZE;
+    let flow = normalize(momentum);
+    let phase = dot(position, flow) * RIPPLE_FREQUENCY - globals.time * settings.river_flow_speed;
+    return normalize(normal - vec3f(flow.x, 0.0, flow.y) * cos(phase) * RIPPLE_STRENGTH);
+}
+
+// 1 inside `range`, fading to 0 over `softness` on both ends.
+fn range_weight(range: vec2f, value: f32, softness: f32) -> f32 {
+    return smoothstep(range.x - softness, range.x + softness, value)
+        * (1.0 - smoothstep(range.y - softness, range.y + softness, value));
+}
+
+// Detail of a splat layer, projected along the axes of the normal so steep slopes are not
+// stretched.
+fn splat_detail(layer: u32, position: vec3f, normal: vec3f) -> f32 {
+    var axes = pow(abs(normal), vec3f(4.0));
+    axes /= axes.x + axes.y + axes.z;
+    let scale = settings.splat_texture_scale;
+    let x = textureSampleLevel(splat_textures, splat_sampler, position.zy * scale, layer, 0.0).r;
+    let y = textureSampleLevel(splat_textures, splat_sampler, position.xz * scale, layer, 0.0).r;
+    let z = textureSampleLevel(splat_textures, splat_sampler, position.xy * scale, layer, 0.0).r;
+    // A second, finer sample breaks up the repetition up close.
+    let detail = textureSampleLevel(splat_textures, splat_sampler, position.xz * scale * 2.0, layer, 0.0).r;
+    return (x * axes.x + y * axes.y + z * axes.z) * (0.75 + 0.5 * detail);
+}
+
+// Blend of the splat layers whose rules match the surface, same as `SplatSettings::weights`.
+fn splat_color(position: vec3f, normal: vec3f) -> vec3f {
+    let uv = heightmap_uv(position.xz);
+    let discharge = textureSampleLevel(discharge_texture, discharge_sampler, uv, 0.0).r;
+    let moisture = min(discharge / settings.river_threshold, 1.0);
+    let erosion = textureSampleLevel(erosion_texture, erosion_sampler, uv, 0.0).r;
+    let slope = 1.0 - normal.y;
+    let blend = settings.splat_blend;
+
+    var color = vec3f(0.0);
+    var total = 0.0;
+    for (var layer = 0u; layer < SPLAT_LAYER_COUNT; layer++) {
+        let rule = settings.splat_rules[layer];
+        let weight = rule.strength
+            * range_weight(rule.altitude_slope.xy, position.y, ALTITUDE_SOFTNESS * blend)
+            * range_weight(rule.altitude_slope.zw, slope, SLOPE_SOFTNESS * blend)
+            * range_weight(rule.moisture_erosion.xy, moisture, MOISTURE_SOFTNESS * blend)
+            * range_weight(rule.moisture_erosion.zw, erosion, EROSION_SOFTNESS * blend);
+        if weight > 0.0 {
+            color += rule.color.rgb * 2.0 * splat_detail(layer, position, normal) * weight;
+            total += weight;
+        }
     }
+    return color / max(total, 1e-4);
+}
 
-    var normal = vec4f();
+// Linear interpolation between five evenly spaced colors, for `t` in `[0, 1]`.
+fn ramp(colors: array<vec3f, 5>, t: f32) -> vec3f {
+    var stops = colors;
+    let x = clamp(t, 0.0, 1.0) * 4.0;
+    let i = min(u32(x), 3u);
+    return mix(stops[i], stops[i + 1u], x - f32(i));
+}
 
-    if vertex.tex_coords.x < 3 {
-        normal = textureSampleLevel(normalmap_topleft_texture, normalmap_topleft_sampler, tex_coords, 0.0);
-    } else {
-        normal = textureSampleLevel(normalmap_bottomright_texture, normalmap_bottomright_sampler, tex_coords, 0.0);
+// Blue to green to yellow to brown to white, for `t` in `[0, 1]`.
+fn height_ramp(t: f32) -> vec3f {
+    return ramp(array(
+        vec3f(0.05, 0.15, 0.5),
+        vec3f(0.15, 0.5, 0.2),
+        vec3f(0.85, 0.8, 0.3),
+        vec3f(0.45, 0.3, 0.15),
+        vec3f(1.0, 1.0, 1.0),
+    ), t);
+}
+
+fn elevation_color(height: f32) -> vec3f {
+    let t = (height - settings.ramp_min) / (settings.ramp_max - settings.ramp_min);
+    switch settings.elevation_ramp {
+        case RAMP_ATLAS: {
+            return ramp(array(
+                vec3f(0.1, 0.35, 0.15),
+                vec3f(0.45, 0.65, 0.3),
+                vec3f(0.85, 0.75, 0.45),
+                vec3f(0.55, 0.35, 0.2),
+                vec3f(0.95, 0.95, 0.95),
+            ), t);
+        }
+        case RAMP_SPECTRAL: {
+            return height_ramp(t);
+        }
+        default: {
+            return vec3f(clamp(t, 0.0, 1.0));
+        }
     }
+}
 
-    out.world_position = mesh_position_local_to_world(model, vec4<f32>(vertex.position, 1.0));
-    out.world_position.y = textureSampleLevel(heightmap_texture, heightmap_sampler, pos, 0.0).r;
+// Black to red to yellow to white, for `t` in `[0, 1]`.
+fn heat_ramp(t: f32) -> vec3f {
+    let x = clamp(t, 0.0, 1.0);
+    return clamp(vec3f(x * 3.0, x * 3.0 - 1.0, x * 3.0 - 2.0), vec3f(0.0), vec3f(1.0));
+}
 
-    out.world_normal = normal.xyz;
+// Blue for negative values, white at zero and red for positive values.
+fn diverging_ramp(value: f32) -> vec3f {
+    let t = clamp(value, -1.0, 1.0);
+    if t < 0.0 {
+        return mix(vec3f(1.0), vec3f(0.1, 0.2, 0.8), -t);
+    }
+    return mix(vec3f(1.0), vec3f(0.8, 0.1, 0.1), t);
+}
 
-    out.position = position_world_to_clip(out.world_position.xyz);
-    out.instance_index = vertex.instance_index;
-    return out;
+// Lines along multiples of `interval` of `value`, about a pixel wide given the screen space
+// derivative `width` of the value.
+fn contour_line(value: f32, interval: f32, width: f32) -> f32 {
+    let distance = abs(fract(value / interval + 0.5) - 0.5) * interval;
+    return 1.0 - smoothstep(0.5 * width, 1.5 * width, distance);
 }
 
+// Negative laplacian of the heightmap, positive on ridges and negative in valleys.
+fn curvature(position: vec2f) -> f32 {
+    let cell = vec2i(floor(position + TERRAIN_SIZE / 2.0));
+    let neighbours = cell_height(cell + vec2i(1, 0)) + cell_height(cell - vec2i(1, 0))
+        + cell_height(cell + vec2i(0, 1)) + cell_height(cell - vec2i(0, 1));
+    return 4.0 * cell_height(cell) - neighbours;
+}
+
+// Color of the selected debug view at a fragment.
+fn debug_color(position: vec3f, normal: vec3f, height_width: f32) -> vec3f {
+    let uv = heightmap_uv(position.xz);
+    let scale = settings.debug_scale;
+    switch settings.debug_view {
+        case DEBUG_HEIGHT: {
+            return height_ramp((position.y - settings.debug_height_min) / (settings.debug_height_max - settings.debug_height_min));
+        }
+        case DEBUG_NORMALS: {
+            return normal * 0.5 + 0.5;
+        }
+        case DEBUG_SLOPE: {
+            return mix(vec3f(0.1, 0.6, 0.1), vec3f(0.8, 0.1, 0.1), clamp((1.0 - normal.y) * 2.0, 0.0, 1.0));
+        }
+        case DEBUG_CURVATURE: {
+            return diverging_ramp(curvature(position.xz) * scale);
+        }
+        case DEBUG_EROSION: {
+            return diverging_ramp(textureSampleLevel(erosion_texture, erosion_sampler, uv, 0.0).r * scale);
+        }
+        case DEBUG_DROP_DENSITY: {
+            let density = textureSampleLevel(momentum_texture, momentum_sampler, uv, 0.0).z;
+            return heat_ramp(1.0 - exp(-density * scale));
+        }
+        case DEBUG_DISCHARGE: {
+            let discharge = textureSampleLevel(discharge_texture, discharge_sampler, uv, 0.0).r;
+            return heat_ramp(1.0 - exp(-discharge * scale));
+        }
+        case DEBUG_CONTOURS: {
+            let line = contour_line(position.y, settings.debug_contour_interval, height_width);
+            return mix(vec3f(0.8), vec3f(0.1), line);
+        }
+        default: {
+            return vec3f(1.0, 0.0, 1.0);
+        }
+    }
+}
 
 @fragment
 fn fragment(
-    in: VertexOutput,
+    vertex_output: VertexOutput,
     @builtin(front_facing) is_front: bool,
 ) -> FragmentOutput {
+    var in = vertex_output;
+    // Derivatives are taken before any branch, where they are still defined.
+    let height_width = fwidth(in.world_position.y);
+    // Rules are evaluated on the terrain itself, before the river ripples.
+    let terrain_normal = surface_normal(in.world_position.xz, in.world_normal);
+    in.world_normal = terrain_normal;
+
+    let river = river_amount(in.world_position.xz);
+    if river > 0.0 {
+        in.world_normal = normalize(mix(in.world_normal, river_normal(in.world_position.xz, in.world_normal), river));
+    }
+
     var pbr_input = pbr_input_from_standard_material(in, is_front);
 
-    if (in.world_normal.y < 0.8) {
+    if settings.splat_enabled != 0u {
+        pbr_input.material.base_color = vec4(splat_color(in.world_position.xyz, terrain_normal), pbr_input.material.base_color.a);
+    } else if (in.world_normal.y < 0.8) {
         pbr_input.material.base_color.g /= 2.0;
     }
 
+    if settings.hypsometric_tint != 0u {
+        let tint = elevation_color(in.world_position.y);
+        pbr_input.material.base_color = vec4(mix(pbr_input.material.base_color.rgb, tint, settings.tint_strength), pbr_input.material.base_color.a);
+    }
+
+    let river_blend = river * settings.river_color.a;
+    pbr_input.material.base_color = vec4(mix(pbr_input.material.base_color.rgb, settings.river_color.rgb, river_blend), pbr_input.material.base_color.a);
+    pbr_input.material.perceptual_roughness = mix(pbr_input.material.perceptual_roughness, RIVER_ROUGHNESS, river_blend);
+
+    // Contours are drawn over the rivers, like on a printed map.
+    if settings.contours != 0u {
+        let minor = contour_line(in.world_position.y, settings.contour_minor_interval, height_width) * MINOR_CONTOUR_OPACITY;
+        let major = contour_line(in.world_position.y, settings.contour_major_interval, height_width * 2.0);
+        let line = max(minor, major) * settings.contour_color.a;
+        pbr_input.material.base_color = vec4(mix(pbr_input.material.base_color.rgb, settings.contour_color.rgb, line), pbr_input.material.base_color.a);
+    }
+
+    // Debug views show the raw values, without the rivers or lighting.
+    if settings.debug_view != 0u {
+        pbr_input.material.base_color = vec4(debug_color(in.world_position.xyz, terrain_normal, height_width), 1.0);
+        pbr_input.material.flags |= STANDARD_MATERIAL_FLAGS_UNLIT_BIT;
+    }
+
     pbr_input.material.base_color = alpha_discard(pbr_input.material, pbr_input.material.base_color);
 
 #ifdef PREPASS_PIPELINE
     let out = deferred_output(in, pbr_input);
 #else
     var out: FragmentOutput;
-    out.color = main_pass_post_lighting_processing(pbr_input, apply_pbr_lighting(pbr_input));
+    if (pbr_input.material.flags & STANDARD_MATERIAL_FLAGS_UNLIT_BIT) == 0u {
+        out.color = apply_pbr_lighting(pbr_input);
+    } else {
+        out.color = pbr_input.material.base_color;
+    }
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
 #endif
 
     return out;
@@ -0,0 +1,109 @@
+#import bevy_pbr::{
+    forward_io::VertexOutput,
+    mesh_functions::{get_world_from_local, mesh_position_local_to_world},
+    mesh_view_bindings::{globals, lights, view},
+    view_transformations::position_world_to_clip,
+}
+
+struct WaterUniform {
+    shallow_color: vec4<f32>,
+    deep_color: vec4<f32>,
+    reflection_color: vec4<f32>,
+    absorption: f32,
+    wave_strength: f32,
+    wave_frequency: f32,
+    wave_speed: f32,
+    inland: u32,
+    inland_threshold: f32,
+    inland_depth: f32,
+};
+
+@group(2) @binding(0) var<uniform> water: WaterUniform;
+@group(2) @binding(1) var heightmap_texture: texture_2d<f32>;
+@group(2) @binding(2) var heightmap_sampler: sampler;
+@group(2) @binding(3) var discharge_texture: texture_2d<f32>;
+@group(2) @binding(4) var discharge_sampler: sampler;
+
+const TERRAIN_SIZE = 256.0;
+// Reflectance of water at normal incidence.
+const WATER_F0 = 0.02;
+// Inland water without enough discharge sinks this far below the terrain, where it is discarded.
+const DRY_DEPTH = 0.1;
+
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+    let model = get_world_from_local(vertex.instance_index);
+    out.world_position = mesh_position_local_to_world(model, vec4<f32>(vertex.position, 1.0));
+
+    // Inland water lies on the terrain, deepening with the discharge above the threshold.
+    if water.inland != 0u {
+        let uv = (out.world_position.xz + TERRAIN_SIZE / 2.0) / TERRAIN_SIZE;
+        let ground = textureSampleLevel(heightmap_texture, heightmap_sampler, uv, 0.0).r;
+        let discharge = textureSampleLevel(discharge_texture, discharge_sampler, uv, 0.0).r;
+        let wet = smoothstep(water.inland_threshold, water.inland_threshold * 2.0, discharge);
+        out.world_position.y = ground - DRY_DEPTH + (water.inland_depth + DRY_DEPTH) * wet;
+    }
+
+    out.world_normal = vec3f(0.0, 1.0, 0.0);
+    out.position = position_world_to_clip(out.world_position.xyz);
+#ifdef VERTEX_OUTPUT_INSTANCE_INDEX
+    out.instance_index = vertex.instance_index;
+#endif
+    return out;
+}
+
+// Slope of a sine wave travelling along `direction`.
+fn wave_slope(position: vec2f, direction: vec2f, frequency: f32, speed: f32) -> vec2f {
+    let phase = dot(position, direction) * frequency + globals.time * speed;
+    return direction * cos(phase) / frequency;
+}
+
+// Normal of a few crossing waves, scaled by the wave settings.
+fn wave_normal(position: vec2f) -> vec3f {
+    let frequency = water.wave_frequency;
+    let speed = water.wave_speed;
+
+    var slope = wave_slope(position, normalize(vec2f(1.0, 0.3)), frequency, speed);
+    slope += wave_slope(position, normalize(vec2f(-0.4, 1.0)), frequency * 1.7, speed * 1.3);
+    slope += wave_slope(position, normalize(vec2f(0.7, -0.8)), frequency * 2.9, speed * 1.7);
+    slope *= water.wave_strength * frequency;
+
+    return normalize(vec3f(-slope.x, 1.0, -slope.y));
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> @location(0) vec4<f32> {
+    let uv = (in.world_position.xz + TERRAIN_SIZE / 2.0) / TERRAIN_SIZE;
+    let ground = textureSampleLevel(heightmap_texture, heightmap_sampler, uv, 0.0).r;
+    let depth = in.world_position.y - ground;
+    if depth <= 0.0 {
+        discard;
+    }
+
+    let absorbed = 1.0 - exp(-water.absorption * depth);
+    let base = mix(water.shallow_color, water.deep_color, absorbed);
+
+    let normal = wave_normal(in.world_position.xz);
+    let view_direction = normalize(view.world_position - in.world_position.xyz);
+    let n_dot_v = max(dot(normal, view_direction), 0.0);
+    let fresnel = WATER_F0 + (1.0 - WATER_F0) * pow(1.0 - n_dot_v, 5.0);
+
+    var color = base.rgb * lights.ambient_color.rgb * view.exposure;
+    if lights.n_directional_lights > 0u {
+        let light = lights.directional_lights[0];
+        let light_color = light.color.rgb * view.exposure;
+        let n_dot_l = max(dot(normal, light.direction_to_light), 0.0);
+        let half_vector = normalize(light.direction_to_light + view_direction);
+        let specular = pow(max(dot(normal, half_vector), 0.0), 256.0) * fresnel;
+        color += base.rgb * n_dot_l * light_color / 3.14159265 + specular * light_color;
+    }
+    color = mix(color, water.reflection_color.rgb, fresnel);
+
+    return vec4(color, mix(base.a, 1.0, fresnel));
+}
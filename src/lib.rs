@@ -9,6 +9,7 @@ use bevy::prelude::*;
 use bevy_panorbit_camera::PanOrbitCameraPlugin;
 use simple_3d_scene::Simple3DScenePlugin;
 use terrain::LowPolyTerrainPlugin;
+use water::WaterPlugin;
 
 #[derive(States, Default, Clone, Eq, PartialEq, Debug, Hash)]
 enum GameState {
@@ -24,6 +25,7 @@ impl Plugin for GamePlugin {
             Simple3DScenePlugin,
             PanOrbitCameraPlugin,
             LowPolyTerrainPlugin,
+            WaterPlugin,
         ));
 
         #[cfg(debug_assertions)]
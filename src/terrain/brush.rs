@@ -0,0 +1,193 @@
+use bevy::{prelude::*, render::extract_resource::ExtractResource, window::PrimaryWindow};
+use bevy_egui::EguiContexts;
+use bevy_panorbit_camera::PanOrbitCamera;
+
+use super::{heights::TerrainHeights, operators::height_range, TERRAIN_SIZE_F32};
+
+/// Distance in cells between two height samples when raycasting the terrain.
+const RAY_STEP: f32 = 0.5;
+
+/// What the `brush` compute pass does to the heights under the brush.
+///
+/// The discriminants match the `BRUSH_*` constants in `erosion.wgsl`.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum BrushTool {
+    #[default]
+    None = 0,
+    Raise = 1,
+    Lower = 2,
+    /// Blends towards the average of the neighbouring cells.
+    Smooth = 3,
+    /// Blends towards the height under the cursor when the stroke started.
+    Flatten = 4,
+    /// Adds simplex noise, fixed in space so strokes accumulate the same pattern.
+    Noise = 5,
+    /// Spawns the erosion droplets inside the brush instead of over the whole grid.
+    ///
+    /// Erosion is paused while this tool is selected and the mouse button is not held.
+    Rain = 6,
+}
+
+#[derive(Resource, Clone, Copy)]
+pub struct BrushSettings {
+    pub tool: BrushTool,
+    /// Radius in cells.
+    pub radius: f32,
+    /// Height change per second at the center of the brush.
+    pub strength: f32,
+    pub noise_frequency: f32,
+}
+
+impl Default for BrushSettings {
+    fn default() -> Self {
+        Self {
+            tool: BrushTool::None,
+            radius: 12.0,
+            strength: 10.0,
+            noise_frequency: 0.05,
+        }
+    }
+}
+
+/// The brush application of the current frame, bumped every frame the mouse button is held.
+#[derive(Resource, Clone, Copy, Default, ExtractResource)]
+pub struct BrushStroke {
+    pub generation: u32,
+    /// Strokes started so far, bumped when the mouse button goes down over the terrain.
+    pub strokes: u32,
+    pub tool: BrushTool,
+    /// Whether the mouse button is held over the terrain this frame.
+    pub active: bool,
+    /// Center of the brush, in cells.
+    pub center: Vec2,
+    pub radius: f32,
+    /// Strength scaled by the frame time.
+    pub strength: f32,
+    pub target_height: f32,
+    pub noise_frequency: f32,
+}
+
+/// Point of the terrain under the mouse cursor.
+#[derive(Resource, Default)]
+pub struct BrushCursor(pub Option<Vec3>);
+
+/// Converts a world position on the terrain mesh to cell coordinates.
+pub fn world_to_cell(position: Vec3) -> Vec2 {
+    position.xz() + TERRAIN_SIZE_F32 / 2.0
+}
+
+/// Marches along `ray` until it goes below the terrain, then refines the hit by bisection.
+pub fn raycast_terrain(ray: Ray3d, heights: &TerrainHeights) -> Option<Vec3> {
+    let (min_height, max_height) = height_range(&heights.heights);
+    let half_size = TERRAIN_SIZE_F32 / 2.0;
+    let min = Vec3::new(-half_size.x, min_height, -half_size.y);
+    let max = Vec3::new(half_size.x, max_height, half_size.y);
+
+    // Slab test against the bounds of the terrain.
+    let inverse_direction = ray.direction.recip();
+    let a = (min - ray.origin) * inverse_direction;
+    let b = (max - ray.origin) * inverse_direction;
+    let near = a.min(b).max_element().max(0.0);
+    let far = a.max(b).min_element();
+    if near > far {
+        return None;
+    }
+
+    let below = |distance: f32| {
+        let point = ray.get_point(distance);
+        point.y <= heights.sample(world_to_cell(point))
+    };
+
+    let mut previous = near;
+    while previous < far {
+        let mut distance = (previous + RAY_STEP).min(far);
+        if below(distance) {
+            let mut above = previous;
+            for _ in 0..16 {
+                let middle = (above + distance) / 2.0;
+                if below(middle) {
+                    distance = middle;
+                } else {
+                    above = middle;
+                }
+            }
+            return Some(ray.get_point(distance));
+        }
+        previous = distance;
+    }
+
+    None
+}
+
+/// Raycasts the cursor against the terrain and records a [`BrushStroke`] while the left mouse
+/// button is held.
+#[allow(clippy::too_many_arguments)]
+pub fn sculpt_terrain(
+    settings: Res<BrushSettings>,
+    heights: Res<TerrainHeights>,
+    mut stroke: ResMut<BrushStroke>,
+    mut cursor: ResMut<BrushCursor>,
+    buttons: Res<ButtonInput<MouseButton>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    time: Res<Time>,
+    window: Query<&Window, With<PrimaryWindow>>,
+    camera: Query<(&Camera, &GlobalTransform)>,
+    mut contexts: EguiContexts,
+) {
+    let (Ok(window), Ok((camera, camera_transform))) = (window.get_single(), camera.get_single())
+    else {
+        return;
+    };
+
+    cursor.0 = window
+        .cursor_position()
+        .and_then(|position| camera.viewport_to_world(camera_transform, position).ok())
+        .and_then(|ray| raycast_terrain(ray, &heights));
+
+    let ctx = contexts.ctx_mut();
+    let blocked = settings.tool == BrushTool::None
+        || ctx.is_pointer_over_area()
+        || ctx.wants_pointer_input()
+        || keys.pressed(KeyCode::AltLeft);
+    let hit = cursor.0.filter(|_| !blocked);
+    let active = hit.is_some() && buttons.pressed(MouseButton::Left);
+
+    // Only write on changes, so the stroke is not extracted every frame.
+    if stroke.tool != settings.tool {
+        stroke.tool = settings.tool;
+    }
+    if stroke.active != active {
+        stroke.active = active;
+        if active {
+            stroke.strokes = stroke.strokes.wrapping_add(1);
+        }
+    }
+    let Some(hit) = hit else {
+        return;
+    };
+
+    if buttons.just_pressed(MouseButton::Left) {
+        stroke.target_height = hit.y;
+    }
+    if active {
+        stroke.generation = stroke.generation.wrapping_add(1);
+        stroke.center = world_to_cell(hit);
+        stroke.radius = settings.radius;
+        stroke.strength = settings.strength * time.delta_secs();
+        stroke.noise_frequency = settings.noise_frequency;
+    }
+}
+
+/// Moves camera orbiting to Alt + left drag while a brush is selected.
+pub fn update_orbit_modifier(
+    settings: Res<BrushSettings>,
+    mut cameras: Query<&mut PanOrbitCamera>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+
+    for mut camera in &mut cameras {
+        camera.modifier_orbit = (settings.tool != BrushTool::None).then_some(KeyCode::AltLeft);
+    }
+}
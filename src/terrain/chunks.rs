@@ -0,0 +1,213 @@
+use bevy::{
+    prelude::*,
+    render::{
+        mesh::Indices, primitives::Aabb, render_asset::RenderAssetUsages,
+        render_resource::PrimitiveTopology,
+    },
+};
+use bevy_panorbit_camera::PanOrbitCamera;
+
+use super::{heights::TerrainHeights, CELL_SIZE, TERRAIN_SIZE, TERRAIN_SIZE_F32};
+
+/// Cells along each side of a chunk.
+pub const CHUNK_SIZE: u32 = 64;
+/// Levels of detail, each with half the vertices per side of the previous one.
+pub const LOD_COUNT: usize = 4;
+/// Depth of the skirts hanging from the chunk borders, which hide the cracks between chunks of
+/// different levels of detail.
+const SKIRT_DEPTH: f32 = 10.0;
+
+const _: () =
+    assert!(TERRAIN_SIZE.x.is_multiple_of(CHUNK_SIZE) && TERRAIN_SIZE.y.is_multiple_of(CHUNK_SIZE));
+const _: () = assert!(CHUNK_SIZE >> (LOD_COUNT - 1) > 0);
+
+/// A square of the terrain, drawn with the shared mesh of its level of detail.
+#[derive(Component)]
+pub struct TerrainChunk {
+    /// First cell of the chunk.
+    pub origin: UVec2,
+    pub lod: usize,
+}
+
+/// The mesh of a chunk at each level of detail, shared by every chunk.
+#[derive(Resource)]
+pub struct TerrainChunkMeshes(pub Vec<Handle<Mesh>>);
+
+#[derive(Resource)]
+pub struct TerrainLod {
+    /// Distance from the camera where chunks drop to the next level of detail. Every further
+    /// level starts twice as far.
+    pub distance: f32,
+}
+
+impl Default for TerrainLod {
+    fn default() -> Self {
+        Self { distance: 96.0 }
+    }
+}
+
+/// Builds the mesh of a chunk with a vertex every `step` cells, in chunk space.
+///
+/// Skirt vertices have a negative height, which the shader adds to the sampled heightmap.
+pub fn build_chunk_mesh(step: u32) -> Mesh {
+    let row = CHUNK_SIZE / step + 1;
+    let last = row - 1;
+
+    let mut positions = Vec::new();
+    for z in 0..row {
+        for x in 0..row {
+            positions.push([
+                (x * step) as f32 * CELL_SIZE,
+                0.0,
+                (z * step) as f32 * CELL_SIZE,
+            ]);
+        }
+    }
+
+    // Top left triangle, then bottom right triangle of each quad.
+    let mut indices = Vec::new();
+    for z in 0..last {
+        for x in 0..last {
+            let a = x + z * row;
+            let b = a + 1;
+            let c = a + row;
+            let d = c + 1;
+            indices.extend_from_slice(&[a, c, b, d, b, c]);
+        }
+    }
+
+    // The border walked so that the skirt triangles face outwards.
+    let border: Vec<u32> = (0..last)
+        .map(|x| (x, 0))
+        .chain((0..last).map(|z| (last, z)))
+        .chain((1..=last).rev().map(|x| (x, last)))
+        .chain((1..=last).rev().map(|z| (0, z)))
+        .map(|(x, z)| x + z * row)
+        .collect();
+    let skirt_start = positions.len() as u32;
+    for &vertex in &border {
+        let [x, _, z] = positions[vertex as usize];
+        positions.push([x, -SKIRT_DEPTH, z]);
+    }
+    for i in 0..border.len() {
+        let next = (i + 1) % border.len();
+        let (p, q) = (border[i], border[next]);
+        let (p_skirt, q_skirt) = (skirt_start + i as u32, skirt_start + next as u32);
+        indices.extend_from_slice(&[p, q, p_skirt, q, q_skirt, p_skirt]);
+    }
+
+    Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::RENDER_WORLD,
+    )
+    .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+    .with_inserted_indices(Indices::U32(indices))
+}
+
+/// Spawns the chunks covering the terrain, all drawn with `material`.
+pub fn spawn_chunks<M: Material>(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    material: Handle<M>,
+) {
+    let chunk_meshes: Vec<_> = (0..LOD_COUNT)
+        .map(|lod| meshes.add(build_chunk_mesh(1 << lod)))
+        .collect();
+
+    let heights = TerrainHeights::default();
+    for z in 0..TERRAIN_SIZE.y / CHUNK_SIZE {
+        for x in 0..TERRAIN_SIZE.x / CHUNK_SIZE {
+            let origin = UVec2::new(x, z) * CHUNK_SIZE;
+            let translation = origin.as_vec2() * CELL_SIZE - TERRAIN_SIZE_F32 / 2.0;
+
+            commands.spawn((
+                TerrainChunk { origin, lod: 0 },
+                Mesh3d(chunk_meshes[0].clone()),
+                MeshMaterial3d(material.clone()),
+                Transform::from_xyz(translation.x, 0.0, translation.y),
+                chunk_bounds(origin, &heights.heights),
+            ));
+        }
+    }
+
+    commands.insert_resource(TerrainChunkMeshes(chunk_meshes));
+}
+
+/// Bounds of a chunk in chunk space, including its skirts.
+fn chunk_bounds(origin: UVec2, heights: &[f32]) -> Aabb {
+    // The heightmap is filtered, so border vertices also depend on the neighbouring cells.
+    let first = origin.saturating_sub(UVec2::ONE);
+    let last = (origin + CHUNK_SIZE).min(TERRAIN_SIZE - 1);
+
+    let (mut min, mut max) = (f32::MAX, f32::MIN);
+    for y in first.y..=last.y {
+        for x in first.x..=last.x {
+            let height = heights[(x + y * TERRAIN_SIZE.x) as usize];
+            min = min.min(height);
+            max = max.max(height);
+        }
+    }
+
+    let size = CHUNK_SIZE as f32 * CELL_SIZE;
+    Aabb::from_min_max(
+        Vec3::new(0.0, min - SKIRT_DEPTH, 0.0),
+        Vec3::new(size, max, size),
+    )
+}
+
+/// Fits the bounds of the chunks to the read back heights whenever the terrain changes, so
+/// frustum culling and shadow cascades see the displaced surface rather than the flat mesh.
+///
+/// [`TerrainHeights`] is only changed by readbacks with different heights, and only the chunks
+/// whose bounds moved are marked as changed.
+pub fn update_chunk_bounds(
+    heights: Res<TerrainHeights>,
+    mut chunks: Query<(&TerrainChunk, &mut Aabb)>,
+) {
+    if !heights.is_changed() {
+        return;
+    }
+
+    for (chunk, mut aabb) in &mut chunks {
+        aabb.set_if_neq(chunk_bounds(chunk.origin, &heights.heights));
+    }
+}
+
+/// Picks the level of detail of each chunk from its distance to the camera.
+pub fn select_chunk_lod(
+    lod: Res<TerrainLod>,
+    chunk_meshes: Res<TerrainChunkMeshes>,
+    cameras: Query<(&GlobalTransform, &Projection), With<PanOrbitCamera>>,
+    mut chunks: Query<(&mut TerrainChunk, &mut Mesh3d, &Transform, &Aabb)>,
+) {
+    let Some((camera, projection)) = cameras.iter().next() else {
+        return;
+    };
+    // Every chunk is seen at the same scale through an orthographic projection, as far as a
+    // perspective camera would be to see the same height.
+    let orthographic_distance = match projection {
+        Projection::Orthographic(orthographic) => {
+            let fov = PerspectiveProjection::default().fov;
+            Some(orthographic.area.height() / (2.0 * (fov / 2.0).tan()))
+        }
+        Projection::Perspective(_) => None,
+    };
+
+    for (mut chunk, mut mesh, transform, aabb) in &mut chunks {
+        let distance = orthographic_distance.unwrap_or_else(|| {
+            let center = transform.transform_point(aabb.center.into());
+            let outside = (camera.translation() - center).abs() - Vec3::from(aabb.half_extents);
+            outside.max(Vec3::ZERO).length()
+        });
+
+        let level = if distance < lod.distance {
+            0
+        } else {
+            ((distance / lod.distance).log2() as usize + 1).min(LOD_COUNT - 1)
+        };
+        if chunk.lod != level {
+            chunk.lod = level;
+            mesh.0 = chunk_meshes.0[level].clone();
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use bevy::prelude::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::hydrology_compute::HydrologyConfig;
+
+fn height_at(heights: &[f32], size: UVec2, cell: UVec2) -> f32 {
+    let cell = cell.min(size - 1);
+    heights[(cell.x + cell.y * size.x) as usize]
+}
+
+/// Normal of the triangle of the cell containing `position`, matching `get_normal` in the shader.
+fn normal_at(heights: &[f32], size: UVec2, position: Vec2) -> Vec3 {
+    let cell = position.as_uvec2();
+    let corner = cell.as_vec2();
+
+    let a = Vec3::new(corner.x, height_at(heights, size, cell), corner.y);
+    let b = Vec3::new(
+        corner.x + 1.0,
+        height_at(heights, size, cell + UVec2::X),
+        corner.y,
+    );
+    let c = Vec3::new(
+        corner.x,
+        height_at(heights, size, cell + UVec2::Y),
+        corner.y + 1.0,
+    );
+    let d = Vec3::new(
+        corner.x + 1.0,
+        height_at(heights, size, cell + 1),
+        corner.y + 1.0,
+    );
+
+    if position.fract().element_sum() < 1.0 {
+        (a - b).cross(c - b).normalize()
+    } else {
+        (d - c).cross(b - c).normalize()
+    }
+}
+
+/// Runs `drops` droplets over the heightmap on the CPU, a port of the `update` compute kernel.
+pub fn erode(heights: &mut [f32], size: UVec2, config: &HydrologyConfig, drops: u32, seed: u64) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let dt = config.dt;
+    let bounds = size.as_vec2();
+
+    for _ in 0..drops {
+        let mut position = Vec2::new(
+            rng.random_range(0..size.x) as f32,
+            rng.random_range(0..size.y) as f32,
+        );
+        let mut speed = Vec2::ZERO;
+        let mut volume = 1.0;
+        let mut sediment = 0.0;
+
+        for _ in 0..config.max_droplet_age {
+            if volume <= config.min_volume {
+                break;
+            }
+
+            let previous = position.as_uvec2();
+            let normal = normal_at(heights, size, position);
+
+            speed += dt * normal.xz() / (volume * config.density);
+            position += dt * speed;
+            speed *= 1.0 - dt * config.friction;
+
+            if position.cmplt(Vec2::ZERO).any() || position.cmpge(bounds).any() {
+                break;
+            }
+
+            let height = height_at(heights, size, previous);
+            let max_sediment =
+                volume * speed.length() * (height - height_at(heights, size, position.as_uvec2()));
+            let sediment_diff = max_sediment.max(0.0) - sediment;
+            let erosion = dt * volume * config.deposition_rate * sediment_diff;
+
+            sediment += dt * config.deposition_rate * sediment_diff;
+            volume *= 1.0 - dt * config.evap_rate;
+
+            heights[(previous.x + previous.y * size.x) as usize] = height - erosion;
+        }
+    }
+}
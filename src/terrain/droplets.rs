@@ -0,0 +1,418 @@
+use bevy::{
+    core_pipeline::core_3d::Transparent3d,
+    ecs::system::{lifetimeless::SRes, SystemParamItem},
+    pbr::{
+        MeshPipeline, MeshPipelineKey, RenderMeshInstances, SetMeshBindGroup, SetMeshViewBindGroup,
+    },
+    prelude::*,
+    render::{
+        extract_component::{ExtractComponent, ExtractComponentPlugin},
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        mesh::{
+            allocator::MeshAllocator, Indices, MeshVertexBufferLayoutRef, PrimitiveTopology,
+            RenderMesh, RenderMeshBufferInfo,
+        },
+        render_asset::{RenderAssetUsages, RenderAssets},
+        render_phase::{
+            AddRenderCommand, DrawFunctions, PhaseItem, PhaseItemExtraIndex, RenderCommand,
+            RenderCommandResult, SetItemPipeline, TrackedRenderPass, ViewSortedRenderPhases,
+        },
+        render_resource::{
+            binding_types::{storage_buffer_read_only_sized, texture_2d, uniform_buffer},
+            BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, PipelineCache,
+            RenderPipelineDescriptor, ShaderStages, SpecializedMeshPipeline,
+            SpecializedMeshPipelineError, SpecializedMeshPipelines, TextureSampleType,
+            UniformBuffer,
+        },
+        renderer::{RenderDevice, RenderQueue},
+        storage::{GpuShaderStorageBuffer, ShaderStorageBuffer},
+        sync_world::MainEntity,
+        texture::GpuImage,
+        view::{ExtractedView, NoFrustumCulling},
+        Render, RenderApp, RenderSet,
+    },
+};
+
+use super::{
+    hydrology_compute::HydrologyConfig, trajectories::TRAJECTORY_COUNT, uniforms::HydrologyImage,
+};
+
+pub use self::particle_uniform::DropletParticleUniform;
+
+const DROPLET_SHADER_PATH: &str = "shaders/droplet_particles.wgsl";
+
+/// Droplets along each row of `update` invocations. Same as `DROPLET_ROW` in `erosion.wgsl`.
+pub const DROPLET_ROW: u32 = 64;
+/// Most droplets [`HydrologyConfig::droplet_count`] can ask for.
+pub const MAX_DROPLET_COUNT: u32 = DROPLET_ROW * 1024;
+/// Fewest droplets, so that all the recorded trajectories have one.
+pub const MIN_DROPLET_COUNT: u32 = TRAJECTORY_COUNT as u32;
+/// Size of a `Droplet` in `erosion.wgsl`: position, speed, volume, sediment, age, spawn count and
+/// stroke, padded to the 8 byte alignment of its `vec2f`s.
+const DROPLET_STRIDE: usize = 40;
+
+/// Buffer of `count` droplet states advanced by the `update` kernel, which runs one invocation per
+/// droplet of the buffer. Zeroed droplets have no volume, so they spawn at their first step.
+pub fn build_droplet_buffer(count: u32) -> ShaderStorageBuffer {
+    ShaderStorageBuffer::new(
+        &vec![0; count as usize * DROPLET_STRIDE],
+        RenderAssetUsages::RENDER_WORLD,
+    )
+}
+
+/// Replaces the droplet buffer when [`HydrologyConfig::droplet_count`] changes, respawning all
+/// droplets.
+pub fn resize_droplet_buffer(
+    config: Res<HydrologyConfig>,
+    hydrology_image: Res<HydrologyImage>,
+    mut buffers: ResMut<Assets<ShaderStorageBuffer>>,
+    mut allocated_count: Local<Option<u32>>,
+) {
+    let count = config.clamped_droplet_count();
+    if *allocated_count == Some(count) {
+        return;
+    }
+
+    *allocated_count = Some(count);
+    buffers.insert(&hydrology_image.droplets, build_droplet_buffer(count));
+}
+
+/// Draws camera facing particles at the live droplets: one quad instanced once per droplet, each
+/// instance reading its droplet from the droplet buffer in the vertex shader.
+pub struct DropletParticlesPlugin;
+
+impl Plugin for DropletParticlesPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<DropletParticleSettings>()
+            .add_plugins(ExtractResourcePlugin::<DropletParticleSettings>::default())
+            .add_plugins(ExtractComponentPlugin::<DropletParticles>::default())
+            .add_systems(
+                Update,
+                (
+                    setup_droplet_particles.run_if(resource_added::<HydrologyImage>),
+                    resize_droplet_buffer.run_if(resource_exists::<HydrologyImage>),
+                ),
+            );
+
+        app.sub_app_mut(RenderApp)
+            .add_render_command::<Transparent3d, DrawDropletParticles>()
+            .init_resource::<SpecializedMeshPipelines<DropletParticlePipeline>>()
+            .add_systems(
+                Render,
+                (
+                    queue_droplet_particles.in_set(RenderSet::QueueMeshes),
+                    prepare_droplet_particles_bind_group.in_set(RenderSet::PrepareBindGroups),
+                ),
+            );
+    }
+
+    fn finish(&self, app: &mut App) {
+        app.sub_app_mut(RenderApp)
+            .init_resource::<DropletParticlePipeline>();
+    }
+}
+
+// Same as `terrain_uniform` in `uniforms.rs`.
+#[allow(dead_code)]
+mod particle_uniform {
+    use bevy::{prelude::*, render::render_resource::ShaderType};
+
+    #[derive(ShaderType, Debug, Clone, Copy, Default)]
+    pub struct DropletParticleUniform {
+        pub color: Vec4,
+        /// Diameter of a full droplet, in cells.
+        pub size: f32,
+    }
+}
+
+/// Droplet particle parameters edited in the UI.
+#[derive(Resource, Clone, PartialEq, ExtractResource)]
+pub struct DropletParticleSettings {
+    pub visible: bool,
+    /// Color of a full droplet, faded with its volume.
+    pub color: LinearRgba,
+    pub size: f32,
+}
+
+impl Default for DropletParticleSettings {
+    fn default() -> Self {
+        Self {
+            visible: false,
+            color: LinearRgba::new(0.3, 0.6, 1.0, 0.9),
+            size: 0.8,
+        }
+    }
+}
+
+impl DropletParticleSettings {
+    fn uniform(&self) -> DropletParticleUniform {
+        DropletParticleUniform {
+            color: self.color.to_vec4(),
+            size: self.size,
+        }
+    }
+}
+
+#[derive(Component, Clone, ExtractComponent)]
+pub struct DropletParticles;
+
+/// Quad with its corners from -1 to 1, drawn once per droplet.
+fn build_particle_mesh() -> Mesh {
+    Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::RENDER_WORLD,
+    )
+    .with_inserted_attribute(
+        Mesh::ATTRIBUTE_POSITION,
+        vec![
+            [-1.0, -1.0, 0.0],
+            [1.0, -1.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [-1.0, 1.0, 0.0],
+        ],
+    )
+    .with_inserted_indices(Indices::U32(vec![0, 1, 2, 0, 2, 3]))
+}
+
+/// Spawns the droplet particles once the droplet buffer exists.
+pub fn setup_droplet_particles(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>) {
+    commands.spawn((
+        DropletParticles,
+        Mesh3d(meshes.add(build_particle_mesh())),
+        Transform::default(),
+        Visibility::default(),
+        // The mesh bounds do not match the particles, which can be anywhere on the terrain.
+        NoFrustumCulling,
+    ));
+}
+
+#[derive(Resource)]
+struct DropletParticlePipeline {
+    shader: Handle<Shader>,
+    mesh_pipeline: MeshPipeline,
+    bind_group_layout: BindGroupLayout,
+}
+
+impl FromWorld for DropletParticlePipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let bind_group_layout = render_device.create_bind_group_layout(
+            "droplet_particles_bind_group_layout",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::VERTEX_FRAGMENT,
+                (
+                    uniform_buffer::<DropletParticleUniform>(false),
+                    texture_2d(TextureSampleType::Float { filterable: false }),
+                    storage_buffer_read_only_sized(false, None),
+                ),
+            ),
+        );
+
+        Self {
+            shader: world.load_asset(DROPLET_SHADER_PATH),
+            mesh_pipeline: world.resource::<MeshPipeline>().clone(),
+            bind_group_layout,
+        }
+    }
+}
+
+impl SpecializedMeshPipeline for DropletParticlePipeline {
+    type Key = MeshPipelineKey;
+
+    fn specialize(
+        &self,
+        key: Self::Key,
+        layout: &MeshVertexBufferLayoutRef,
+    ) -> Result<RenderPipelineDescriptor, SpecializedMeshPipelineError> {
+        let mut descriptor = self.mesh_pipeline.specialize(key, layout)?;
+        descriptor.label = Some("droplet_particles_pipeline".into());
+        descriptor.vertex.shader = self.shader.clone();
+        descriptor.fragment.as_mut().unwrap().shader = self.shader.clone();
+        descriptor.layout.push(self.bind_group_layout.clone());
+        Ok(descriptor)
+    }
+}
+
+/// Particle parameters, heightmap and droplet buffer bound to the particle shader.
+#[derive(Resource)]
+struct DropletParticlesBindGroup(BindGroup);
+
+#[allow(clippy::too_many_arguments)]
+fn prepare_droplet_particles_bind_group(
+    mut commands: Commands,
+    pipeline: Res<DropletParticlePipeline>,
+    settings: Res<DropletParticleSettings>,
+    hydrology_image: Option<Res<HydrologyImage>>,
+    gpu_images: Res<RenderAssets<GpuImage>>,
+    gpu_buffers: Res<RenderAssets<GpuShaderStorageBuffer>>,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut uniform_buffer: Local<UniformBuffer<DropletParticleUniform>>,
+) {
+    let Some(hydrology_image) = hydrology_image else {
+        return;
+    };
+    let (Some(heightmap), Some(droplets)) = (
+        gpu_images.get(&hydrology_image.heightmap),
+        gpu_buffers.get(&hydrology_image.droplets),
+    ) else {
+        return;
+    };
+
+    uniform_buffer.set(settings.uniform());
+    uniform_buffer.write_buffer(&render_device, &render_queue);
+
+    let bind_group = render_device.create_bind_group(
+        "droplet_particles_bind_group",
+        &pipeline.bind_group_layout,
+        &BindGroupEntries::sequential((
+            uniform_buffer.binding().unwrap(),
+            &heightmap.texture_view,
+            droplets.buffer.as_entire_binding(),
+        )),
+    );
+    commands.insert_resource(DropletParticlesBindGroup(bind_group));
+}
+
+#[allow(clippy::too_many_arguments)]
+fn queue_droplet_particles(
+    draw_functions: Res<DrawFunctions<Transparent3d>>,
+    pipeline: Res<DropletParticlePipeline>,
+    mut pipelines: ResMut<SpecializedMeshPipelines<DropletParticlePipeline>>,
+    pipeline_cache: Res<PipelineCache>,
+    settings: Res<DropletParticleSettings>,
+    meshes: Res<RenderAssets<RenderMesh>>,
+    render_mesh_instances: Res<RenderMeshInstances>,
+    particles: Query<(Entity, &MainEntity), With<DropletParticles>>,
+    mut transparent_phases: ResMut<ViewSortedRenderPhases<Transparent3d>>,
+    views: Query<(Entity, &ExtractedView, &Msaa)>,
+) {
+    if !settings.visible {
+        return;
+    }
+
+    let draw_function = draw_functions.read().id::<DrawDropletParticles>();
+    for (view_entity, view, msaa) in &views {
+        let Some(transparent_phase) = transparent_phases.get_mut(&view_entity) else {
+            continue;
+        };
+
+        let view_key = MeshPipelineKey::from_msaa_samples(msaa.samples())
+            | MeshPipelineKey::from_hdr(view.hdr)
+            | MeshPipelineKey::BLEND_ALPHA;
+        let rangefinder = view.rangefinder3d();
+        for (entity, main_entity) in &particles {
+            let Some(mesh_instance) = render_mesh_instances.render_mesh_queue_data(*main_entity)
+            else {
+                continue;
+            };
+            let Some(mesh) = meshes.get(mesh_instance.mesh_asset_id) else {
+                continue;
+            };
+            let key =
+                view_key | MeshPipelineKey::from_primitive_topology(mesh.primitive_topology());
+            let Ok(pipeline) = pipelines.specialize(&pipeline_cache, &pipeline, key, &mesh.layout)
+            else {
+                continue;
+            };
+            transparent_phase.add(Transparent3d {
+                entity: (entity, *main_entity),
+                pipeline,
+                draw_function,
+                distance: rangefinder.distance_translation(&mesh_instance.translation),
+                batch_range: 0..1,
+                extra_index: PhaseItemExtraIndex::NONE,
+            });
+        }
+    }
+}
+
+type DrawDropletParticles = (
+    SetItemPipeline,
+    SetMeshViewBindGroup<0>,
+    SetMeshBindGroup<1>,
+    SetDropletParticlesBindGroup<2>,
+    DrawDropletInstances,
+);
+
+struct SetDropletParticlesBindGroup<const I: usize>;
+
+impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetDropletParticlesBindGroup<I> {
+    type Param = Option<SRes<DropletParticlesBindGroup>>;
+    type ViewQuery = ();
+    type ItemQuery = ();
+
+    fn render<'w>(
+        _item: &P,
+        _view: (),
+        _entity: Option<()>,
+        bind_group: SystemParamItem<'w, '_, Self::Param>,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let Some(bind_group) = bind_group else {
+            return RenderCommandResult::Skip;
+        };
+        pass.set_bind_group(I, &bind_group.into_inner().0, &[]);
+        RenderCommandResult::Success
+    }
+}
+
+/// Draws the particle quad once per droplet, the instance index being the droplet index.
+struct DrawDropletInstances;
+
+impl<P: PhaseItem> RenderCommand<P> for DrawDropletInstances {
+    type Param = (
+        SRes<RenderAssets<RenderMesh>>,
+        SRes<RenderMeshInstances>,
+        SRes<MeshAllocator>,
+        SRes<HydrologyConfig>,
+    );
+    type ViewQuery = ();
+    type ItemQuery = ();
+
+    fn render<'w>(
+        item: &P,
+        _view: (),
+        _entity: Option<()>,
+        (meshes, render_mesh_instances, mesh_allocator, config): SystemParamItem<
+            'w,
+            '_,
+            Self::Param,
+        >,
+        pass: &mut TrackedRenderPass<'w>,
+    ) -> RenderCommandResult {
+        let mesh_allocator = mesh_allocator.into_inner();
+
+        let Some(mesh_instance) = render_mesh_instances.render_mesh_queue_data(item.main_entity())
+        else {
+            return RenderCommandResult::Skip;
+        };
+        let Some(gpu_mesh) = meshes.into_inner().get(mesh_instance.mesh_asset_id) else {
+            return RenderCommandResult::Skip;
+        };
+        let Some(vertex_slice) = mesh_allocator.mesh_vertex_slice(&mesh_instance.mesh_asset_id)
+        else {
+            return RenderCommandResult::Skip;
+        };
+        let RenderMeshBufferInfo::Indexed {
+            index_format,
+            count,
+        } = &gpu_mesh.buffer_info
+        else {
+            return RenderCommandResult::Skip;
+        };
+        let Some(index_slice) = mesh_allocator.mesh_index_slice(&mesh_instance.mesh_asset_id)
+        else {
+            return RenderCommandResult::Skip;
+        };
+
+        pass.set_vertex_buffer(0, vertex_slice.buffer.slice(..));
+        pass.set_index_buffer(index_slice.buffer.slice(..), 0, *index_format);
+        pass.draw_indexed(
+            index_slice.range.start..(index_slice.range.start + count),
+            vertex_slice.range.start as i32,
+            0..config.clamped_droplet_count(),
+        );
+        RenderCommandResult::Success
+    }
+}
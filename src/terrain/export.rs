@@ -0,0 +1,399 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use bevy::prelude::*;
+use serde_json::json;
+
+use super::{heights::TerrainHeights, CELL_SIZE, TERRAIN_SIZE, TERRAIN_SIZE_F32};
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum ExportFormat {
+    /// Binary glTF 2.0.
+    #[default]
+    Gltf,
+    Obj,
+    /// Binary STL.
+    Stl,
+}
+
+impl ExportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Gltf => "glb",
+            Self::Obj => "obj",
+            Self::Stl => "stl",
+        }
+    }
+}
+
+/// An indexed triangle mesh of the heightmap, in the same coordinates as the rendered terrain.
+pub struct ExportMesh {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub uvs: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+}
+
+/// Depth of the base of the STL solid below the lowest point of the terrain.
+const SOLID_BASE_THICKNESS: f32 = 2.0;
+
+/// Sample coordinates along one axis, every `step` cells and always including the last one.
+fn axis_samples(size: u32, step: u32) -> Vec<u32> {
+    let mut samples: Vec<u32> = (0..size).step_by(step.max(1) as usize).collect();
+    if samples.last() != Some(&(size - 1)) {
+        samples.push(size - 1);
+    }
+    samples
+}
+
+impl ExportMesh {
+    /// Builds a grid mesh with a vertex every `step` cells, so a step above 1 decimates it.
+    pub fn from_heights(heights: &[f32], step: u32) -> Self {
+        let xs = axis_samples(TERRAIN_SIZE.x, step);
+        let zs = axis_samples(TERRAIN_SIZE.y, step);
+        let max = (TERRAIN_SIZE - 1).as_vec2();
+
+        let mut positions = Vec::with_capacity(xs.len() * zs.len());
+        let mut uvs = Vec::with_capacity(xs.len() * zs.len());
+        for &z in &zs {
+            for &x in &xs {
+                let height = heights[(x + z * TERRAIN_SIZE.x) as usize];
+                positions.push([
+                    x as f32 * CELL_SIZE - TERRAIN_SIZE_F32.x / 2.0,
+                    height,
+                    z as f32 * CELL_SIZE - TERRAIN_SIZE_F32.y / 2.0,
+                ]);
+                uvs.push([x as f32 / max.x, z as f32 / max.y]);
+            }
+        }
+
+        let row = xs.len() as u32;
+        let mut indices = Vec::with_capacity((xs.len() - 1) * (zs.len() - 1) * 6);
+        for z in 0..zs.len() as u32 - 1 {
+            for x in 0..row - 1 {
+                let a = x + z * row;
+                let b = a + 1;
+                let c = a + row;
+                let d = c + 1;
+                indices.extend_from_slice(&[a, c, b, b, c, d]);
+            }
+        }
+
+        // Area weighted average of the normals of the triangles around each vertex.
+        let mut normals = vec![Vec3::ZERO; positions.len()];
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| Vec3::from(positions[triangle[i] as usize]));
+            let normal = (b - a).cross(c - a);
+            for &i in triangle {
+                normals[i as usize] += normal;
+            }
+        }
+
+        Self {
+            positions,
+            normals: normals
+                .into_iter()
+                .map(|normal| normal.normalize_or(Vec3::Y).to_array())
+                .collect(),
+            uvs,
+            indices,
+        }
+    }
+
+    pub fn write(&self, format: ExportFormat, writer: &mut impl Write) -> io::Result<()> {
+        match format {
+            ExportFormat::Gltf => self.write_glb(writer),
+            ExportFormat::Obj => self.write_obj(writer),
+            ExportFormat::Stl => self.write_stl(writer),
+        }
+    }
+
+    fn write_obj(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(writer, "o terrain")?;
+        for [x, y, z] in &self.positions {
+            writeln!(writer, "v {x} {y} {z}")?;
+        }
+        for [u, v] in &self.uvs {
+            writeln!(writer, "vt {u} {v}")?;
+        }
+        for [x, y, z] in &self.normals {
+            writeln!(writer, "vn {x} {y} {z}")?;
+        }
+        // OBJ indices start at 1.
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [triangle[0] + 1, triangle[1] + 1, triangle[2] + 1];
+            writeln!(writer, "f {a}/{a}/{a} {b}/{b}/{b} {c}/{c}/{c}")?;
+        }
+        Ok(())
+    }
+
+    /// Closes the surface with side walls down to `base` and a bottom cap, so it encloses a
+    /// volume. Returns the positions and the triangle indices, all facing outwards.
+    fn closed_solid(&self, base: f32) -> (Vec<Vec3>, Vec<u32>) {
+        let mut positions: Vec<Vec3> = self.positions.iter().copied().map(Vec3::from).collect();
+        let mut indices = self.indices.clone();
+
+        // Edges used by a single triangle are on the border, going around it.
+        let edges: HashSet<(u32, u32)> = self
+            .indices
+            .chunks_exact(3)
+            .flat_map(|t| [(t[0], t[1]), (t[1], t[2]), (t[2], t[0])])
+            .collect();
+        let mut border: Vec<(u32, u32)> = edges
+            .iter()
+            .copied()
+            .filter(|(a, b)| !edges.contains(&(*b, *a)))
+            .collect();
+        border.sort_unstable();
+
+        let mut below = HashMap::new();
+        let mut base_vertex = |positions: &mut Vec<Vec3>, top: u32| {
+            *below.entry(top).or_insert_with(|| {
+                let position = positions[top as usize];
+                positions.push(Vec3::new(position.x, base, position.z));
+                positions.len() as u32 - 1
+            })
+        };
+
+        let center = positions.len() as u32;
+        let middle = border
+            .iter()
+            .map(|(a, _)| positions[*a as usize])
+            .sum::<Vec3>()
+            / border.len().max(1) as f32;
+        positions.push(Vec3::new(middle.x, base, middle.z));
+
+        for (a, b) in border {
+            let (base_a, base_b) = (
+                base_vertex(&mut positions, a),
+                base_vertex(&mut positions, b),
+            );
+            indices.extend_from_slice(&[b, a, base_a, b, base_a, base_b]);
+            indices.extend_from_slice(&[center, base_b, base_a]);
+        }
+
+        (positions, indices)
+    }
+
+    fn write_stl(&self, writer: &mut impl Write) -> io::Result<()> {
+        let lowest = self
+            .positions
+            .iter()
+            .map(|position| position[1])
+            .fold(f32::INFINITY, f32::min);
+        let (positions, indices) = self.closed_solid(lowest - SOLID_BASE_THICKNESS);
+
+        writer.write_all(&[0; 80])?;
+        writer.write_all(&(indices.len() as u32 / 3).to_le_bytes())?;
+
+        for triangle in indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| positions[triangle[i] as usize]);
+            let normal = (b - a).cross(c - a).normalize_or_zero();
+            for vector in [normal, a, b, c] {
+                for value in vector.to_array() {
+                    writer.write_all(&value.to_le_bytes())?;
+                }
+            }
+            // Attribute byte count, unused.
+            writer.write_all(&[0; 2])?;
+        }
+        Ok(())
+    }
+
+    fn write_glb(&self, writer: &mut impl Write) -> io::Result<()> {
+        const ARRAY_BUFFER: u32 = 34962;
+        const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+        const FLOAT: u32 = 5126;
+        const UNSIGNED_INT: u32 = 5125;
+
+        let views: [(Vec<u8>, u32); 4] = [
+            (
+                self.positions
+                    .iter()
+                    .flatten()
+                    .flat_map(|v| v.to_le_bytes())
+                    .collect(),
+                ARRAY_BUFFER,
+            ),
+            (
+                self.normals
+                    .iter()
+                    .flatten()
+                    .flat_map(|v| v.to_le_bytes())
+                    .collect(),
+                ARRAY_BUFFER,
+            ),
+            (
+                self.uvs
+                    .iter()
+                    .flatten()
+                    .flat_map(|v| v.to_le_bytes())
+                    .collect(),
+                ARRAY_BUFFER,
+            ),
+            (
+                self.indices.iter().flat_map(|i| i.to_le_bytes()).collect(),
+                ELEMENT_ARRAY_BUFFER,
+            ),
+        ];
+
+        let mut binary = Vec::new();
+        let mut buffer_views = Vec::new();
+        for (bytes, target) in &views {
+            buffer_views.push(json!({
+                "buffer": 0,
+                "byteOffset": binary.len(),
+                "byteLength": bytes.len(),
+                "target": target,
+            }));
+            binary.extend_from_slice(bytes);
+        }
+
+        let (min, max) = self.positions.iter().fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), position| (min.min((*position).into()), max.max((*position).into())),
+        );
+        let vertex_count = self.positions.len();
+        let document = json!({
+            "asset": { "version": "2.0", "generator": "bevy_hydrology" },
+            "scene": 0,
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [{ "mesh": 0, "name": "terrain" }],
+            "meshes": [{
+                "name": "terrain",
+                "primitives": [{
+                    "attributes": { "POSITION": 0, "NORMAL": 1, "TEXCOORD_0": 2 },
+                    "indices": 3,
+                    "mode": 4,
+                }],
+            }],
+            "buffers": [{ "byteLength": binary.len() }],
+            "bufferViews": buffer_views,
+            "accessors": [
+                {
+                    "bufferView": 0,
+                    "componentType": FLOAT,
+                    "count": vertex_count,
+                    "type": "VEC3",
+                    "min": min.to_array(),
+                    "max": max.to_array(),
+                },
+                { "bufferView": 1, "componentType": FLOAT, "count": vertex_count, "type": "VEC3" },
+                { "bufferView": 2, "componentType": FLOAT, "count": vertex_count, "type": "VEC2" },
+                {
+                    "bufferView": 3,
+                    "componentType": UNSIGNED_INT,
+                    "count": self.indices.len(),
+                    "type": "SCALAR",
+                },
+            ],
+        });
+
+        // Chunks are padded to 4 bytes, the JSON with spaces and the binary data with zeros.
+        let mut json = serde_json::to_vec(&document).map_err(io::Error::other)?;
+        json.resize(json.len().next_multiple_of(4), b' ');
+        binary.resize(binary.len().next_multiple_of(4), 0);
+
+        let length = 12 + 8 + json.len() + 8 + binary.len();
+        writer.write_all(b"glTF")?;
+        writer.write_all(&2u32.to_le_bytes())?;
+        writer.write_all(&(length as u32).to_le_bytes())?;
+        writer.write_all(&(json.len() as u32).to_le_bytes())?;
+        writer.write_all(b"JSON")?;
+        writer.write_all(&json)?;
+        writer.write_all(&(binary.len() as u32).to_le_bytes())?;
+        writer.write_all(b"BIN\0")?;
+        writer.write_all(&binary)
+    }
+}
+
+/// Settings of the mesh export in the UI.
+#[derive(Resource)]
+pub struct MeshExport {
+    /// Path of the exported file, without the extension of the format.
+    pub path: String,
+    pub format: ExportFormat,
+    /// Distance in cells between exported vertices.
+    pub step: u32,
+    pub pending: bool,
+    /// Result of the last export, shown in the UI.
+    pub status: String,
+}
+
+impl Default for MeshExport {
+    fn default() -> Self {
+        Self {
+            path: "terrain".to_owned(),
+            format: ExportFormat::default(),
+            step: 1,
+            pending: false,
+            status: String::new(),
+        }
+    }
+}
+
+/// Writes the read back heightmap as a mesh when requested from the UI.
+pub fn export_mesh(mut export: ResMut<MeshExport>, heights: Res<TerrainHeights>) {
+    if !std::mem::take(&mut export.pending) {
+        return;
+    }
+
+    let path = format!("{}.{}", export.path, export.format.extension());
+    let mesh = ExportMesh::from_heights(&heights.heights, export.step);
+    let result = File::create(Path::new(&path)).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        mesh.write(export.format, &mut writer)?;
+        writer.flush()
+    });
+
+    export.status = match result {
+        Ok(()) => {
+            info!("Exported terrain to {path}");
+            format!("Exported {path} ({} triangles)", mesh.indices.len() / 3)
+        }
+        Err(error) => {
+            error!("Could not export terrain to {path}: {error}");
+            format!("Could not export {path}: {error}")
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stl_solid_is_closed_and_faces_outwards() {
+        let heights: Vec<f32> = (0..TERRAIN_SIZE.x * TERRAIN_SIZE.y)
+            .map(|i| ((i % 37) as f32 * 0.3).sin() * 5.0)
+            .collect();
+        let mesh = ExportMesh::from_heights(&heights, 16);
+        let (positions, indices) = mesh.closed_solid(-10.0);
+
+        // Every edge is shared by exactly two triangles, going in opposite directions.
+        let mut edges = HashMap::new();
+        for t in indices.chunks_exact(3) {
+            for edge in [(t[0], t[1]), (t[1], t[2]), (t[2], t[0])] {
+                *edges.entry(edge).or_insert(0) += 1;
+            }
+        }
+        for (&(a, b), &count) in &edges {
+            assert_eq!(count, 1, "edge {a} -> {b} is used {count} times");
+            assert_eq!(edges.get(&(b, a)), Some(&1), "edge {a} -> {b} is open");
+        }
+
+        // Outward facing triangles enclose a positive volume.
+        let volume: f32 = indices
+            .chunks_exact(3)
+            .map(|t| {
+                let [a, b, c] = [0, 1, 2].map(|i| positions[t[i] as usize]);
+                a.dot(b.cross(c)) / 6.0
+            })
+            .sum();
+        assert!(volume > 0.0);
+    }
+}
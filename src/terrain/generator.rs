@@ -0,0 +1,101 @@
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin, ScaleBias, Seedable, Simplex, Turbulence};
+
+use super::{
+    graph::{TerrainGraph, TerrainGraphSource},
+    hydrology_compute::HydrologyConfig,
+    masks::{apply_mask, build_mask, TerrainMaskImage},
+    uniforms::HeightmapUpload,
+    TerrainBuildConfig, TerrainGenerator, TerrainRebuild, TERRAIN_SIZE,
+};
+
+type BoxedNoise = Box<dyn NoiseFn<f64, 2> + Send + Sync>;
+
+/// Builds the noise graph that gives the initial height for a cell coordinate.
+pub fn build_noise(config: &TerrainBuildConfig) -> BoxedNoise {
+    let seed = config.seed as u32;
+
+    let mut source: BoxedNoise = Box::new(
+        Fbm::<Simplex>::new(seed)
+            .set_octaves(6)
+            .set_frequency(config.base_frequency as f64),
+    );
+
+    for level in 0..config.warp_levels.min(2) {
+        source = Box::new(
+            Turbulence::<_, Perlin>::new(source)
+                .set_seed(seed.wrapping_add(level + 1))
+                .set_frequency(config.warp_frequency as f64)
+                .set_power(config.warp_strength as f64)
+                .set_roughness(4),
+        );
+    }
+
+    // `Fbm` is normalized to [-1, 1], whereas the compute shader sums the octaves unnormalized.
+    Box::new(
+        ScaleBias::new(source)
+            .set_scale(2.0 * config.base_amplitude as f64)
+            .set_bias(20.0),
+    )
+}
+
+pub fn generate_heightmap(config: &TerrainBuildConfig, mask: &[f32]) -> Vec<f32> {
+    let noise = build_noise(config);
+
+    (0..TERRAIN_SIZE.y)
+        .flat_map(|y| (0..TERRAIN_SIZE.x).map(move |x| (x, y)))
+        .zip(mask)
+        .map(|((x, y), mask)| apply_mask(noise.get([x as f64, y as f64]) as f32, *mask, config))
+        .collect()
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn generate_cpu_terrain(
+    config: Res<TerrainBuildConfig>,
+    hydrology_config: Res<HydrologyConfig>,
+    rebuild: Res<TerrainRebuild>,
+    mask_image: Res<TerrainMaskImage>,
+    graph_source: Res<TerrainGraphSource>,
+    graphs: Res<Assets<TerrainGraph>>,
+    images: Res<Assets<Image>>,
+    mut upload: ResMut<HeightmapUpload>,
+    mut built_generation: Local<Option<u32>>,
+) {
+    // Only a requested rebuild regenerates the terrain, so loading a project or undoing keeps
+    // the uploaded heights.
+    if *built_generation == Some(rebuild.generation) {
+        return;
+    }
+    *built_generation = Some(rebuild.generation);
+
+    match config.generator {
+        TerrainGenerator::Gpu => {}
+        TerrainGenerator::Cpu => {
+            let mask = build_mask(
+                &config,
+                mask_image
+                    .image
+                    .as_ref()
+                    .and_then(|handle| images.get(handle)),
+            );
+            upload.push_terrain(Arc::new(generate_heightmap(&config, &mask)));
+        }
+        TerrainGenerator::Graph => {
+            let Some(graph) = graph_source
+                .graph
+                .as_ref()
+                .and_then(|handle| graphs.get(handle))
+            else {
+                warn!("Terrain graph '{}' is not loaded yet", graph_source.path);
+                return;
+            };
+
+            match graph.evaluate(&images, &hydrology_config) {
+                Ok(heights) => upload.push_terrain(Arc::new(heights)),
+                Err(error) => error!("Could not evaluate terrain graph: {error}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,539 @@
+use std::{error::Error, fmt};
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    prelude::*,
+};
+use noise::{Fbm, MultiFractal, NoiseFn, Perlin, RidgedMulti, Simplex, Worley};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    cpu_erosion::erode,
+    heights::sample_bilinear,
+    hydrology_compute::HydrologyConfig,
+    masks::{mask_value, sample_luminance, TerrainMask},
+    operators::{map_normalized, terrace, HeightCurve},
+    TERRAIN_SIZE,
+};
+
+/// Index of a node in [`TerrainGraph::nodes`].
+pub type NodeId = usize;
+
+/// A terrain recipe, loaded from `.terrain.ron` or `.terrain.json` files.
+///
+/// Nodes can only use the output of nodes listed before them, so the list is evaluated in order.
+#[derive(Asset, TypePath, Clone, Debug, Serialize, Deserialize)]
+pub struct TerrainGraph {
+    pub nodes: Vec<GraphNode>,
+    pub output: NodeId,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum NoiseKind {
+    Simplex,
+    Perlin,
+    Ridged,
+    Worley,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum BlendMode {
+    Add,
+    Subtract,
+    Multiply,
+    Min,
+    Max,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum GraphNode {
+    Constant(f32),
+    /// Noise in [-1, 1], sampled at the cell coordinates.
+    Noise {
+        kind: NoiseKind,
+        seed: u32,
+        frequency: f32,
+        octaves: usize,
+    },
+    /// Luminance of an image in [0, 1], stretched over the grid.
+    Image {
+        path: String,
+        #[serde(skip)]
+        handle: Handle<Image>,
+    },
+    Mask {
+        shape: TerrainMask,
+        radius: f32,
+        falloff: f32,
+    },
+    /// `input * scale + bias`.
+    Remap {
+        input: NodeId,
+        scale: f32,
+        bias: f32,
+    },
+    Blend {
+        a: NodeId,
+        b: NodeId,
+        mode: BlendMode,
+    },
+    /// Linear interpolation from `a` to `b`, controlled by `weight` in [0, 1].
+    Mix {
+        a: NodeId,
+        b: NodeId,
+        weight: NodeId,
+    },
+    /// Offsets the sampling coordinates of `input` by fractal noise.
+    Warp {
+        input: NodeId,
+        seed: u32,
+        frequency: f32,
+        strength: f32,
+    },
+    /// Remaps heights, normalized over the range of `input`, through a curve.
+    Curve {
+        input: NodeId,
+        curve: HeightCurve,
+    },
+    Terrace {
+        input: NodeId,
+        steps: u32,
+        sharpness: f32,
+    },
+    /// Droplet erosion on the CPU, using the current [`HydrologyConfig`].
+    Erosion {
+        input: NodeId,
+        drops: u32,
+        seed: u64,
+    },
+}
+
+#[derive(Debug)]
+pub enum TerrainGraphError {
+    /// A node refers to itself or to a node listed after it.
+    InvalidInput {
+        node: NodeId,
+        input: NodeId,
+    },
+    InvalidOutput(NodeId),
+    ImageNotLoaded(String),
+}
+
+impl fmt::Display for TerrainGraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidInput { node, input } => {
+                write!(
+                    f,
+                    "node {node} uses node {input}, which is not listed before it"
+                )
+            }
+            Self::InvalidOutput(node) => write!(f, "output node {node} does not exist"),
+            Self::ImageNotLoaded(path) => write!(f, "image '{path}' is not loaded"),
+        }
+    }
+}
+
+impl Error for TerrainGraphError {}
+
+fn cells() -> impl Iterator<Item = UVec2> {
+    (0..TERRAIN_SIZE.y).flat_map(|y| (0..TERRAIN_SIZE.x).map(move |x| UVec2::new(x, y)))
+}
+
+fn sample_noise(noise: &impl NoiseFn<f64, 2>) -> Vec<f32> {
+    cells()
+        .map(|cell| noise.get([cell.x as f64, cell.y as f64]) as f32)
+        .collect()
+}
+
+impl GraphNode {
+    fn inputs(&self) -> Vec<NodeId> {
+        match self {
+            Self::Constant(_) | Self::Noise { .. } | Self::Image { .. } | Self::Mask { .. } => {
+                vec![]
+            }
+            Self::Remap { input, .. }
+            | Self::Warp { input, .. }
+            | Self::Curve { input, .. }
+            | Self::Terrace { input, .. }
+            | Self::Erosion { input, .. } => vec![*input],
+            Self::Blend { a, b, .. } => vec![*a, *b],
+            Self::Mix { a, b, weight } => vec![*a, *b, *weight],
+        }
+    }
+
+    fn evaluate(
+        &self,
+        outputs: &[Vec<f32>],
+        images: &Assets<Image>,
+        hydrology_config: &HydrologyConfig,
+    ) -> Result<Vec<f32>, TerrainGraphError> {
+        let cell_count = (TERRAIN_SIZE.x * TERRAIN_SIZE.y) as usize;
+
+        Ok(match self {
+            Self::Constant(value) => vec![*value; cell_count],
+            Self::Noise {
+                kind,
+                seed,
+                frequency,
+                octaves,
+            } => {
+                let frequency = *frequency as f64;
+                match kind {
+                    NoiseKind::Simplex => sample_noise(
+                        &Fbm::<Simplex>::new(*seed)
+                            .set_octaves(*octaves)
+                            .set_frequency(frequency),
+                    ),
+                    NoiseKind::Perlin => sample_noise(
+                        &Fbm::<Perlin>::new(*seed)
+                            .set_octaves(*octaves)
+                            .set_frequency(frequency),
+                    ),
+                    NoiseKind::Ridged => sample_noise(
+                        &RidgedMulti::<Perlin>::new(*seed)
+                            .set_octaves(*octaves)
+                            .set_frequency(frequency),
+                    ),
+                    NoiseKind::Worley => sample_noise(&Worley::new(*seed).set_frequency(frequency)),
+                }
+            }
+            Self::Image { path, handle } => {
+                let image = images
+                    .get(handle)
+                    .ok_or_else(|| TerrainGraphError::ImageNotLoaded(path.clone()))?;
+                cells().map(|cell| sample_luminance(image, cell)).collect()
+            }
+            Self::Mask {
+                shape,
+                radius,
+                falloff,
+            } => cells()
+                .map(|cell| mask_value(*shape, *radius, *falloff, cell, None))
+                .collect(),
+            Self::Remap { input, scale, bias } => {
+                outputs[*input].iter().map(|h| h * scale + bias).collect()
+            }
+            Self::Blend { a, b, mode } => outputs[*a]
+                .iter()
+                .zip(&outputs[*b])
+                .map(|(a, b)| match mode {
+                    BlendMode::Add => a + b,
+                    BlendMode::Subtract => a - b,
+                    BlendMode::Multiply => a * b,
+                    BlendMode::Min => a.min(*b),
+                    BlendMode::Max => a.max(*b),
+                })
+                .collect(),
+            Self::Mix { a, b, weight } => outputs[*a]
+                .iter()
+                .zip(&outputs[*b])
+                .zip(&outputs[*weight])
+                .map(|((a, b), weight)| a.lerp(*b, weight.clamp(0.0, 1.0)))
+                .collect(),
+            Self::Warp {
+                input,
+                seed,
+                frequency,
+                strength,
+            } => {
+                let warp_x = Fbm::<Simplex>::new(*seed)
+                    .set_octaves(4)
+                    .set_frequency(*frequency as f64);
+                let warp_y = Fbm::<Simplex>::new(seed.wrapping_add(1))
+                    .set_octaves(4)
+                    .set_frequency(*frequency as f64);
+                cells()
+                    .map(|cell| {
+                        let point = [cell.x as f64, cell.y as f64];
+                        let offset = Vec2::new(warp_x.get(point) as f32, warp_y.get(point) as f32);
+                        sample_bilinear(&outputs[*input], cell.as_vec2() + *strength * offset)
+                    })
+                    .collect()
+            }
+            Self::Curve { input, curve } => {
+                let mut heights = outputs[*input].clone();
+                map_normalized(&mut heights, |t| curve.sample(t));
+                heights
+            }
+            Self::Terrace {
+                input,
+                steps,
+                sharpness,
+            } => {
+                let mut heights = outputs[*input].clone();
+                map_normalized(&mut heights, |t| terrace(t, *steps, *sharpness));
+                heights
+            }
+            Self::Erosion { input, drops, seed } => {
+                let mut heights = outputs[*input].clone();
+                erode(&mut heights, TERRAIN_SIZE, hydrology_config, *drops, *seed);
+                heights
+            }
+        })
+    }
+}
+
+impl TerrainGraph {
+    /// Evaluates all nodes on the CPU and returns the heights of the output node.
+    pub fn evaluate(
+        &self,
+        images: &Assets<Image>,
+        hydrology_config: &HydrologyConfig,
+    ) -> Result<Vec<f32>, TerrainGraphError> {
+        if self.output >= self.nodes.len() {
+            return Err(TerrainGraphError::InvalidOutput(self.output));
+        }
+
+        let mut outputs: Vec<Vec<f32>> = Vec::with_capacity(self.nodes.len());
+        for (node_id, node) in self.nodes.iter().enumerate() {
+            if let Some(input) = node.inputs().into_iter().find(|input| *input >= node_id) {
+                return Err(TerrainGraphError::InvalidInput {
+                    node: node_id,
+                    input,
+                });
+            }
+
+            let heights = node.evaluate(&outputs, images, hydrology_config)?;
+            outputs.push(heights);
+        }
+
+        Ok(outputs.swap_remove(self.output))
+    }
+}
+
+/// The graph used by [`super::TerrainGenerator::Graph`].
+#[derive(Resource, Default)]
+pub struct TerrainGraphSource {
+    pub path: String,
+    pub graph: Option<Handle<TerrainGraph>>,
+}
+
+#[derive(Debug)]
+pub enum TerrainGraphLoaderError {
+    Io(std::io::Error),
+    Ron(ron::error::SpannedError),
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for TerrainGraphLoaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "could not read terrain graph: {error}"),
+            Self::Ron(error) => write!(f, "could not parse RON terrain graph: {error}"),
+            Self::Json(error) => write!(f, "could not parse JSON terrain graph: {error}"),
+        }
+    }
+}
+
+impl Error for TerrainGraphLoaderError {}
+
+#[derive(Default)]
+pub struct TerrainGraphLoader;
+
+impl AssetLoader for TerrainGraphLoader {
+    type Asset = TerrainGraph;
+    type Settings = ();
+    type Error = TerrainGraphLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<TerrainGraph, Self::Error> {
+        let mut bytes = Vec::new();
+        reader
+            .read_to_end(&mut bytes)
+            .await
+            .map_err(TerrainGraphLoaderError::Io)?;
+
+        let mut graph: TerrainGraph = if load_context
+            .path()
+            .extension()
+            .is_some_and(|ext| ext == "json")
+        {
+            serde_json::from_slice(&bytes).map_err(TerrainGraphLoaderError::Json)?
+        } else {
+            ron::de::from_bytes(&bytes).map_err(TerrainGraphLoaderError::Ron)?
+        };
+
+        for node in &mut graph.nodes {
+            if let GraphNode::Image { path, handle } = node {
+                *handle = load_context.load(path.clone());
+            }
+        }
+
+        Ok(graph)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["terrain.ron", "terrain.json"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CELL_COUNT: usize = (TERRAIN_SIZE.x * TERRAIN_SIZE.y) as usize;
+
+    fn evaluate(nodes: Vec<GraphNode>, output: NodeId) -> Result<Vec<f32>, TerrainGraphError> {
+        TerrainGraph { nodes, output }.evaluate(&Assets::default(), &HydrologyConfig::default())
+    }
+
+    fn range(heights: &[f32]) -> (f32, f32) {
+        heights.iter().fold((f32::MAX, f32::MIN), |(min, max), h| {
+            (min.min(*h), max.max(*h))
+        })
+    }
+
+    #[test]
+    fn nodes_use_the_outputs_of_earlier_nodes() {
+        let heights = evaluate(
+            vec![
+                GraphNode::Constant(2.0),
+                GraphNode::Constant(3.0),
+                GraphNode::Blend {
+                    a: 0,
+                    b: 1,
+                    mode: BlendMode::Multiply,
+                },
+                GraphNode::Remap {
+                    input: 2,
+                    scale: 0.5,
+                    bias: 1.0,
+                },
+            ],
+            3,
+        )
+        .unwrap();
+
+        assert_eq!(heights.len(), CELL_COUNT);
+        assert!(heights.iter().all(|h| *h == 4.0));
+    }
+
+    #[test]
+    fn output_can_be_any_node() {
+        let heights = evaluate(
+            vec![
+                GraphNode::Constant(2.0),
+                GraphNode::Remap {
+                    input: 0,
+                    scale: 2.0,
+                    bias: 0.0,
+                },
+            ],
+            0,
+        )
+        .unwrap();
+
+        assert!(heights.iter().all(|h| *h == 2.0));
+    }
+
+    #[test]
+    fn later_and_self_inputs_are_rejected() {
+        let forward = evaluate(
+            vec![
+                GraphNode::Remap {
+                    input: 1,
+                    scale: 1.0,
+                    bias: 0.0,
+                },
+                GraphNode::Constant(1.0),
+            ],
+            0,
+        );
+        assert!(matches!(
+            forward,
+            Err(TerrainGraphError::InvalidInput { node: 0, input: 1 })
+        ));
+
+        let itself = evaluate(
+            vec![
+                GraphNode::Constant(1.0),
+                GraphNode::Blend {
+                    a: 0,
+                    b: 1,
+                    mode: BlendMode::Add,
+                },
+            ],
+            1,
+        );
+        assert!(matches!(
+            itself,
+            Err(TerrainGraphError::InvalidInput { node: 1, input: 1 })
+        ));
+    }
+
+    #[test]
+    fn missing_output_is_rejected() {
+        let result = evaluate(vec![GraphNode::Constant(1.0)], 1);
+        assert!(matches!(result, Err(TerrainGraphError::InvalidOutput(1))));
+    }
+
+    #[test]
+    fn mask_and_mix_stay_in_range() {
+        let heights = evaluate(
+            vec![
+                GraphNode::Mask {
+                    shape: TerrainMask::Island,
+                    radius: 0.3,
+                    falloff: 0.4,
+                },
+                GraphNode::Constant(10.0),
+                GraphNode::Constant(20.0),
+                // Weights outside [0, 1] are clamped.
+                GraphNode::Remap {
+                    input: 0,
+                    scale: 3.0,
+                    bias: -1.0,
+                },
+                GraphNode::Mix {
+                    a: 1,
+                    b: 2,
+                    weight: 3,
+                },
+            ],
+            4,
+        )
+        .unwrap();
+
+        let (min, max) = range(&heights);
+        assert_eq!(min, 10.0);
+        assert_eq!(max, 20.0);
+    }
+
+    #[test]
+    fn curve_and_terrace_keep_the_input_range() {
+        let noise = GraphNode::Noise {
+            kind: NoiseKind::Perlin,
+            seed: 7,
+            frequency: 0.02,
+            octaves: 4,
+        };
+        let input = evaluate(vec![noise.clone()], 0).unwrap();
+        let (input_min, input_max) = range(&input);
+        assert!(input_min < input_max);
+
+        for node in [
+            GraphNode::Curve {
+                input: 0,
+                curve: HeightCurve {
+                    points: vec![[0.0, 0.0], [0.5, 0.2], [1.0, 1.0]],
+                    ..default()
+                },
+            },
+            GraphNode::Terrace {
+                input: 0,
+                steps: 4,
+                sharpness: 0.5,
+            },
+        ] {
+            let heights = evaluate(vec![noise.clone(), node], 1).unwrap();
+            let (min, max) = range(&heights);
+            assert!((min - input_min).abs() < 1e-4);
+            assert!((max - input_max).abs() < 1e-4);
+        }
+    }
+}
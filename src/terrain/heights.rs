@@ -0,0 +1,92 @@
+use bevy::{prelude::*, render::gpu_readback::ReadbackComplete};
+
+use super::TERRAIN_SIZE;
+
+/// CPU copy of the heightmap texture, read back from the GPU every frame.
+///
+/// Only marked as changed when the heights differ, so systems can react to terrain changes.
+#[derive(Resource, PartialEq)]
+pub struct TerrainHeights {
+    pub heights: Vec<f32>,
+}
+
+impl Default for TerrainHeights {
+    fn default() -> Self {
+        Self {
+            heights: vec![0.0; (TERRAIN_SIZE.x * TERRAIN_SIZE.y) as usize],
+        }
+    }
+}
+
+impl TerrainHeights {
+    /// Bilinear height at a position in cells, clamped to the grid.
+    pub fn sample(&self, position: Vec2) -> f32 {
+        sample_bilinear(&self.heights, position)
+    }
+}
+
+/// Bilinear sample of a heightmap, clamped to the grid.
+pub fn sample_bilinear(heights: &[f32], position: Vec2) -> f32 {
+    let max = TERRAIN_SIZE - 1;
+    let position = position.clamp(Vec2::ZERO, max.as_vec2());
+    let cell = position.as_uvec2().min(max - 1);
+    let f = position - cell.as_vec2();
+    let at = |cell: UVec2| heights[(cell.x + cell.y * TERRAIN_SIZE.x) as usize];
+
+    let top = at(cell).lerp(at(cell + UVec2::X), f.x);
+    let bottom = at(cell + UVec2::Y).lerp(at(cell + 1), f.x);
+    top.lerp(bottom, f.y)
+}
+
+/// CPU copy of the discharge and erosion maps, read back from the GPU every frame.
+#[derive(Resource)]
+pub struct TerrainFlow {
+    pub discharge: Vec<f32>,
+    /// Height removed by erosion, negative where sediment was deposited.
+    pub erosion: Vec<f32>,
+}
+
+impl Default for TerrainFlow {
+    fn default() -> Self {
+        Self {
+            discharge: vec![0.0; (TERRAIN_SIZE.x * TERRAIN_SIZE.y) as usize],
+            erosion: vec![0.0; (TERRAIN_SIZE.x * TERRAIN_SIZE.y) as usize],
+        }
+    }
+}
+
+/// Values of a read back `R32Float` texture of [`TERRAIN_SIZE`].
+fn read_r32_float(data: &[u8]) -> Vec<f32> {
+    // Rows are padded to the copy alignment of the GPU.
+    let row_size = TERRAIN_SIZE.x as usize * 4;
+    let stride = data.len() / TERRAIN_SIZE.y as usize;
+
+    data.chunks_exact(stride)
+        .flat_map(|row| row[..row_size].chunks_exact(4))
+        .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect()
+}
+
+/// Observer of the heightmap [`bevy::render::gpu_readback::Readback`].
+pub fn read_heights(trigger: Trigger<ReadbackComplete>, mut heights: ResMut<TerrainHeights>) {
+    heights.set_if_neq(TerrainHeights {
+        heights: read_r32_float(&trigger),
+    });
+}
+
+/// Observer of the discharge map [`bevy::render::gpu_readback::Readback`].
+pub fn read_discharge(trigger: Trigger<ReadbackComplete>, mut flow: ResMut<TerrainFlow>) {
+    let discharge = read_r32_float(&trigger);
+    // Only marked as changed when the map differs, as readbacks arrive every frame.
+    if flow.discharge != discharge {
+        flow.discharge = discharge;
+    }
+}
+
+/// Observer of the erosion map [`bevy::render::gpu_readback::Readback`].
+pub fn read_erosion(trigger: Trigger<ReadbackComplete>, mut flow: ResMut<TerrainFlow>) {
+    let erosion = read_r32_float(&trigger);
+    if flow.erosion != erosion {
+        flow.erosion = erosion;
+    }
+}
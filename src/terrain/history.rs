@@ -0,0 +1,176 @@
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use bevy_egui::EguiContexts;
+
+use super::{
+    brush::{BrushStroke, BrushTool},
+    heights::TerrainHeights,
+    operators::ApplyHeightOperators,
+    uniforms::HeightmapUpload,
+    TerrainRebuild,
+};
+
+/// The heightmap before an action was applied.
+pub struct Snapshot {
+    pub label: String,
+    pub heights: Arc<Vec<f32>>,
+}
+
+impl Snapshot {
+    fn size(&self) -> usize {
+        self.heights.len() * size_of::<f32>()
+    }
+}
+
+/// Undo and redo stacks of heightmap snapshots, taken from [`TerrainHeights`].
+#[derive(Resource)]
+pub struct TerrainHistory {
+    /// Heights before each applied action, oldest first.
+    pub undo: Vec<Snapshot>,
+    /// Heights after each undone action, most recently undone last.
+    pub redo: Vec<Snapshot>,
+    /// Oldest snapshots are dropped once the stacks use more bytes than this.
+    pub memory_limit: usize,
+    /// Seconds of erosion recorded as a single action.
+    pub erosion_batch_seconds: f32,
+    erosion_elapsed: f32,
+}
+
+impl Default for TerrainHistory {
+    fn default() -> Self {
+        Self {
+            undo: Vec::new(),
+            redo: Vec::new(),
+            memory_limit: 64 * 1024 * 1024,
+            erosion_batch_seconds: 10.0,
+            erosion_elapsed: 0.0,
+        }
+    }
+}
+
+impl TerrainHistory {
+    pub fn memory_usage(&self) -> usize {
+        self.undo.iter().chain(&self.redo).map(Snapshot::size).sum()
+    }
+
+    /// Records the current heights as the state before `label`, discarding the redo stack.
+    pub fn push(&mut self, label: impl Into<String>, heights: &TerrainHeights) {
+        self.redo.clear();
+        self.erosion_elapsed = 0.0;
+        self.undo.push(Snapshot {
+            label: label.into(),
+            heights: Arc::new(heights.heights.clone()),
+        });
+
+        while self.memory_usage() > self.memory_limit && !self.undo.is_empty() {
+            self.undo.remove(0);
+        }
+    }
+
+    /// Undoes `steps` actions and uploads the resulting heights.
+    pub fn undo(
+        &mut self,
+        steps: usize,
+        heights: &mut TerrainHeights,
+        upload: &mut HeightmapUpload,
+    ) {
+        Self::travel(&mut self.undo, &mut self.redo, steps, heights, upload);
+        self.erosion_elapsed = 0.0;
+    }
+
+    /// Redoes `steps` undone actions and uploads the resulting heights.
+    pub fn redo(
+        &mut self,
+        steps: usize,
+        heights: &mut TerrainHeights,
+        upload: &mut HeightmapUpload,
+    ) {
+        Self::travel(&mut self.redo, &mut self.undo, steps, heights, upload);
+        self.erosion_elapsed = 0.0;
+    }
+
+    /// Moves snapshots from `from` to `to`, swapping each one with the heights it replaces.
+    fn travel(
+        from: &mut Vec<Snapshot>,
+        to: &mut Vec<Snapshot>,
+        steps: usize,
+        heights: &mut TerrainHeights,
+        upload: &mut HeightmapUpload,
+    ) {
+        let steps = steps.min(from.len());
+        if steps == 0 {
+            return;
+        }
+
+        // The readback lags behind, so keep the CPU copy in sync with what is uploaded.
+        let mut current = Arc::new(std::mem::take(&mut heights.heights));
+        for _ in 0..steps {
+            let snapshot = from.pop().unwrap();
+            to.push(Snapshot {
+                label: snapshot.label,
+                heights: current,
+            });
+            current = snapshot.heights;
+        }
+
+        heights.heights = current.as_ref().clone();
+        upload.push(current);
+    }
+}
+
+/// Takes a snapshot before rebuilds, height operators, brush strokes and every erosion batch.
+#[allow(clippy::too_many_arguments)]
+pub fn record_history(
+    mut history: ResMut<TerrainHistory>,
+    heights: Res<TerrainHeights>,
+    rebuild: Res<TerrainRebuild>,
+    apply_operators: Res<ApplyHeightOperators>,
+    stroke: Res<BrushStroke>,
+    time: Res<Time>,
+    mut stroke_active: Local<bool>,
+    mut seen_generations: Local<Option<(u32, u32)>>,
+) {
+    let stroke_started = stroke.active && !*stroke_active;
+    *stroke_active = stroke.active;
+
+    // Rebuild and operator generations, starting from the ones seen on the first frame.
+    let generations = (rebuild.generation, apply_operators.generation);
+    let (rebuild_seen, operators_seen) =
+        seen_generations.replace(generations).unwrap_or(generations);
+
+    if rebuild.generation != rebuild_seen {
+        history.push("Rebuild", &heights);
+    } else if apply_operators.generation != operators_seen {
+        history.push("Height operators", &heights);
+    } else if stroke_started {
+        history.push(format!("{:?} brush", stroke.tool), &heights);
+    } else if stroke.tool != BrushTool::Rain {
+        // Erosion is paused while the rain brush is selected.
+        history.erosion_elapsed += time.delta_secs();
+        if history.erosion_elapsed >= history.erosion_batch_seconds {
+            history.push("Erosion", &heights);
+        }
+    }
+}
+
+/// Ctrl+Z undoes, Ctrl+Y or Ctrl+Shift+Z redoes.
+pub fn history_shortcuts(
+    keys: Res<ButtonInput<KeyCode>>,
+    mut history: ResMut<TerrainHistory>,
+    mut heights: ResMut<TerrainHeights>,
+    mut upload: ResMut<HeightmapUpload>,
+    mut contexts: EguiContexts,
+) {
+    let control = keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    let shift = keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    if !control || contexts.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+
+    if keys.just_pressed(KeyCode::KeyY) || (shift && keys.just_pressed(KeyCode::KeyZ)) {
+        history.redo(1, &mut heights, &mut upload);
+    } else if keys.just_pressed(KeyCode::KeyZ) {
+        history.undo(1, &mut heights, &mut upload);
+    }
+}
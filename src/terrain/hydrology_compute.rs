@@ -1,4 +1,4 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, num::NonZeroU64};
 
 use bevy::{
     ecs::system::ResMut,
@@ -8,26 +8,39 @@ use bevy::{
         render_asset::RenderAssets,
         render_graph::{Node, NodeRunError, RenderGraph, RenderGraphContext, RenderLabel},
         render_resource::{
-            binding_types::uniform_buffer, AsBindGroup, BindGroup, BindGroupEntries,
-            BindGroupLayout, BindGroupLayoutEntries, CachedComputePipelineId, CachedPipelineState,
-            ComputePassDescriptor, ComputePipelineDescriptor, PipelineCache, ShaderStages,
+            binding_types::{storage_buffer_sized, texture_storage_2d, uniform_buffer},
+            AsBindGroup, BindGroup, BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries,
+            Buffer, BufferDescriptor, BufferInitDescriptor, BufferUsages, CachedComputePipelineId,
+            CachedPipelineState, ComputePassDescriptor, ComputePipelineDescriptor, Extent3d,
+            ImageDataLayout, PipelineCache, ShaderStages, StorageTextureAccess, TextureDescriptor,
+            TextureDimension, TextureFormat, TextureUsages, TextureViewDescriptor,
         },
         renderer::{RenderContext, RenderDevice, RenderQueue},
+        storage::GpuShaderStorageBuffer,
         texture::GpuImage,
         Extract, Render, RenderApp, RenderSet,
     },
 };
-use rand::{thread_rng, Rng};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
 
 use super::{
-    uniforms::{HydrologyImage, TerrainUniform, TerrainUniformBuffer},
-    TerrainBuildConfig,
+    brush::{BrushStroke, BrushTool},
+    droplets::{DROPLET_ROW, MAX_DROPLET_COUNT, MIN_DROPLET_COUNT},
+    operators::{ApplyHeightOperators, CurveInterpolation, HeightOperators, MAX_CURVE_POINTS},
+    trajectories::TrajectorySettings,
+    uniforms::{HeightmapUpload, HydrologyImage, TerrainUniform, TerrainUniformBuffer},
+    TerrainBuildConfig, TerrainGenerator, TerrainRebuild,
 };
 
 const SIZE: (u32, u32) = (256, 256);
 const WORKGROUP_SIZE: u32 = 8;
+/// Discharge, two momentum components and drop visits per cell, as `i32`s.
+const FLOW_TRACK_SIZE: u64 = SIZE.0 as u64 * SIZE.1 as u64 * 4 * 4;
 
-#[derive(Resource, Clone, Copy)]
+/// Missing fields are taken from the defaults when deserializing, so presets can be partial.
+#[derive(Resource, Clone, Copy, TypePath, Serialize, Deserialize)]
+#[serde(default)]
 pub struct HydrologyConfig {
     // volume_factor: f32,
     pub dt: f32,
@@ -36,9 +49,12 @@ pub struct HydrologyConfig {
     pub deposition_rate: f32,
     pub min_volume: f32,
     pub friction: f32,
-    pub drops_per_frame_per_chunk: u32,
-    pub drop_count: u32,
-    pub max_drops: u32,
+    /// Droplets simulated at once, each kept alive across frames.
+    pub droplet_count: u32,
+    /// Steps each droplet takes per frame, which bounds the erosion work of a frame.
+    pub droplet_steps_per_frame: u32,
+    /// Steps after which a droplet that is still alive respawns.
+    pub max_droplet_age: u32,
 }
 
 impl Default for HydrologyConfig {
@@ -51,19 +67,28 @@ impl Default for HydrologyConfig {
             deposition_rate: 0.1,
             friction: 0.05,
             min_volume: 0.05,
-            drops_per_frame_per_chunk: 1000,
-            drop_count: 0,
-            max_drops: 200_000,
+            droplet_count: 4096,
+            droplet_steps_per_frame: 64,
+            max_droplet_age: 1500,
         }
     }
 }
 
+impl HydrologyConfig {
+    /// [`Self::droplet_count`] within the droplets the simulation supports.
+    pub fn clamped_droplet_count(&self) -> u32 {
+        self.droplet_count
+            .clamp(MIN_DROPLET_COUNT, MAX_DROPLET_COUNT)
+    }
+}
+
 #[derive(Resource)]
 pub struct HydrologyUniformBindGroup(BindGroup);
 
 #[derive(Resource)]
 pub struct HydrologyImageBindGroup(BindGroup);
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn prepare_uniforms_bind_group(
     mut commands: Commands,
     pipeline: Res<HydrologyPipeline>,
@@ -71,24 +96,55 @@ pub(crate) fn prepare_uniforms_bind_group(
     mut terrain_uniform_buffer: ResMut<TerrainUniformBuffer>,
     terrain_build_config: Res<TerrainBuildConfig>,
     hydrology_config: Res<HydrologyConfig>,
+    height_operators: Res<HeightOperators>,
+    brush_stroke: Res<BrushStroke>,
+    trajectory_settings: Res<TrajectorySettings>,
     render_device: Res<RenderDevice>,
 ) {
     let buffer = terrain_uniform_buffer.buffer.get_mut();
-    let mut rng = thread_rng();
+    let mut rng = rand::rng();
 
     buffer.noise_seed = terrain_build_config.seed;
     buffer.noise_amplitude = terrain_build_config.base_amplitude;
     buffer.noise_base_frequency = terrain_build_config.base_frequency;
-    buffer.time_seconds = rng.gen_range(0.0..1e6); // * time.elapsed_seconds_wrapped();
+    buffer.noise_warp_levels = terrain_build_config.warp_levels;
+    buffer.noise_warp_strength = terrain_build_config.warp_strength;
+    buffer.noise_warp_frequency = terrain_build_config.warp_frequency;
+    buffer.sea_cutoff = terrain_build_config.sea_cutoff.into();
+    buffer.sea_level = terrain_build_config.sea_level;
+    buffer.time_seconds = rng.random_range(0.0..1e6); // * time.elapsed_seconds_wrapped();
     buffer.dt = hydrology_config.dt;
     buffer.density = hydrology_config.density;
     buffer.evap_rate = hydrology_config.evap_rate;
     buffer.deposition_rate = hydrology_config.deposition_rate;
     buffer.min_volume = hydrology_config.min_volume;
     buffer.friction = hydrology_config.friction;
-    buffer.drops_per_frame_per_chunck = hydrology_config.drops_per_frame_per_chunk;
-    buffer.drop_count = hydrology_config.drop_count;
-    buffer.max_drops = hydrology_config.max_drops;
+    buffer.droplet_steps_per_frame = hydrology_config.droplet_steps_per_frame;
+    buffer.max_droplet_age = hydrology_config.max_droplet_age;
+
+    let points =
+        &height_operators.curve.points[..height_operators.curve.points.len().min(MAX_CURVE_POINTS)];
+    buffer.curve_points = [Vec4::ZERO; MAX_CURVE_POINTS / 2];
+    for (pair, chunk) in buffer.curve_points.iter_mut().zip(points.chunks(2)) {
+        let second = chunk.get(1).copied().unwrap_or_default();
+        *pair = Vec4::new(chunk[0][0], chunk[0][1], second[0], second[1]);
+    }
+    buffer.curve_point_count = points.len() as u32;
+    buffer.curve_smooth =
+        (height_operators.curve.interpolation == CurveInterpolation::Smooth).into();
+    buffer.terrace_steps = height_operators.terrace_steps;
+    buffer.terrace_sharpness = height_operators.terrace_sharpness;
+    buffer.plateau_min = height_operators.plateau_min;
+    buffer.plateau_max = height_operators.plateau_max;
+    buffer.brush_center = brush_stroke.center;
+    buffer.brush_radius = brush_stroke.radius;
+    buffer.brush_strength = brush_stroke.strength;
+    buffer.brush_tool = brush_stroke.tool as u32;
+    buffer.brush_active = brush_stroke.active.into();
+    buffer.brush_target_height = brush_stroke.target_height;
+    buffer.brush_noise_frequency = brush_stroke.noise_frequency;
+    buffer.brush_strokes = brush_stroke.strokes;
+    buffer.record_trajectories = trajectory_settings.enabled.into();
 
     terrain_uniform_buffer
         .buffer
@@ -106,6 +162,7 @@ pub(crate) fn prepare_textures_bind_group(
     mut commands: Commands,
     pipeline: Res<HydrologyPipeline>,
     gpu_images: Res<RenderAssets<GpuImage>>,
+    gpu_buffers: Res<RenderAssets<GpuShaderStorageBuffer>>,
     hydrology_image: Res<HydrologyImage>,
     render_device: Res<RenderDevice>,
 ) {
@@ -114,6 +171,12 @@ pub(crate) fn prepare_textures_bind_group(
     let normalmap_bottomright_view = gpu_images
         .get(&hydrology_image.normalmap_bottomright)
         .unwrap();
+    let mask_view = gpu_images.get(&hydrology_image.mask).unwrap();
+    let discharge_view = gpu_images.get(&hydrology_image.discharge).unwrap();
+    let momentum_view = gpu_images.get(&hydrology_image.momentum).unwrap();
+    let erosion_view = gpu_images.get(&hydrology_image.erosion).unwrap();
+    let trajectories = gpu_buffers.get(&hydrology_image.trajectories).unwrap();
+    let droplets = gpu_buffers.get(&hydrology_image.droplets).unwrap();
 
     let bind_group = render_device.create_bind_group(
         None,
@@ -122,6 +185,12 @@ pub(crate) fn prepare_textures_bind_group(
             &heightmap_view.texture_view,
             &normalmap_topleft_view.texture_view,
             &normalmap_bottomright_view.texture_view,
+            &mask_view.texture_view,
+            &discharge_view.texture_view,
+            &momentum_view.texture_view,
+            &erosion_view.texture_view,
+            trajectories.buffer.as_entire_binding(),
+            droplets.buffer.as_entire_binding(),
         )),
     );
     commands.insert_resource(HydrologyImageBindGroup(bind_group));
@@ -131,8 +200,25 @@ pub(crate) fn prepare_textures_bind_group(
 pub struct HydrologyPipeline {
     pub texture_bind_group_layout: BindGroupLayout,
     pub uniform_bind_group_layout: BindGroupLayout,
+    /// Min and max height of the heightmap, as order-preserving `u32`s written with atomics.
+    height_range_buffer: Buffer,
+    /// Height range buffer, the brush output texture and the flow of the current frame, only used
+    /// by the compute passes.
+    scratch_bind_group: BindGroup,
     init_pipeline: CachedComputePipelineId,
     update_pipeline: CachedComputePipelineId,
+    normals_pipeline: CachedComputePipelineId,
+    height_range_pipeline: CachedComputePipelineId,
+    operators_pipeline: CachedComputePipelineId,
+    brush_pipeline: CachedComputePipelineId,
+    apply_brush_pipeline: CachedComputePipelineId,
+    flow_maps_pipeline: CachedComputePipelineId,
+}
+
+/// Initial contents of the height range buffer, before `find_height_range` lowers the min and
+/// raises the max.
+fn height_range_reset() -> Vec<u8> {
+    [u32::MAX.to_le_bytes(), 0u32.to_le_bytes()].concat()
 }
 
 impl FromWorld for HydrologyPipeline {
@@ -150,36 +236,94 @@ impl FromWorld for HydrologyPipeline {
         let uniform_bind_group_layout =
             render_device.create_bind_group_layout("uniform_bind_group_layout", &entries);
 
-        let init_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-            zero_initialize_workgroup_memory: false,
-            label: None,
-            layout: vec![
-                uniform_bind_group_layout.clone(),
-                texture_bind_group_layout.clone(),
-            ],
-            push_constant_ranges: Vec::new(),
-            shader: shader.clone(),
-            shader_defs: vec![],
-            entry_point: Cow::from("init"),
+        let scratch_bind_group_layout = render_device.create_bind_group_layout(
+            "scratch_bind_group_layout",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::COMPUTE,
+                (
+                    storage_buffer_sized(false, NonZeroU64::new(8)),
+                    texture_storage_2d(TextureFormat::R32Float, StorageTextureAccess::ReadWrite),
+                    storage_buffer_sized(false, NonZeroU64::new(FLOW_TRACK_SIZE)),
+                ),
+            ),
+        );
+        let height_range_buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            label: Some("height_range_buffer"),
+            contents: &height_range_reset(),
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+        });
+        // The `brush` pass writes here first, so it can read the neighbours of a cell while
+        // smoothing without seeing heights it already changed.
+        let brush_texture = render_device.create_texture(&TextureDescriptor {
+            label: Some("brush_texture"),
+            size: Extent3d {
+                width: SIZE.0,
+                height: SIZE.1,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::R32Float,
+            usage: TextureUsages::STORAGE_BINDING,
+            view_formats: &[],
         });
-        let update_pipeline = pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
-            zero_initialize_workgroup_memory: false,
-            label: None,
-            layout: vec![
-                uniform_bind_group_layout.clone(),
-                texture_bind_group_layout.clone(),
-            ],
-            push_constant_ranges: Vec::new(),
-            shader,
-            shader_defs: vec![],
-            entry_point: Cow::from("update"),
+        // Discharge, momentum and visits of the drops of one frame, as fixed point values summed
+        // with atomics. `flow_maps` blends them into the flow textures and clears them.
+        let flow_track_buffer = render_device.create_buffer(&BufferDescriptor {
+            label: Some("flow_track_buffer"),
+            size: FLOW_TRACK_SIZE,
+            usage: BufferUsages::STORAGE,
+            mapped_at_creation: false,
         });
+        let scratch_bind_group = render_device.create_bind_group(
+            "scratch_bind_group",
+            &scratch_bind_group_layout,
+            &BindGroupEntries::sequential((
+                height_range_buffer.as_entire_binding(),
+                &brush_texture.create_view(&TextureViewDescriptor::default()),
+                flow_track_buffer.as_entire_binding(),
+            )),
+        );
+
+        let queue_pipeline = |entry_point: &'static str| {
+            pipeline_cache.queue_compute_pipeline(ComputePipelineDescriptor {
+                zero_initialize_workgroup_memory: false,
+                label: None,
+                layout: vec![
+                    uniform_bind_group_layout.clone(),
+                    texture_bind_group_layout.clone(),
+                    scratch_bind_group_layout.clone(),
+                ],
+                push_constant_ranges: Vec::new(),
+                shader: shader.clone(),
+                shader_defs: vec![],
+                entry_point: Cow::from(entry_point),
+            })
+        };
+
+        let init_pipeline = queue_pipeline("init");
+        let update_pipeline = queue_pipeline("update");
+        let normals_pipeline = queue_pipeline("normals");
+        let height_range_pipeline = queue_pipeline("find_height_range");
+        let operators_pipeline = queue_pipeline("operators");
+        let brush_pipeline = queue_pipeline("brush");
+        let apply_brush_pipeline = queue_pipeline("apply_brush");
+        let flow_maps_pipeline = queue_pipeline("flow_maps");
 
         HydrologyPipeline {
             texture_bind_group_layout,
             uniform_bind_group_layout,
+            height_range_buffer,
+            scratch_bind_group,
             init_pipeline,
             update_pipeline,
+            normals_pipeline,
+            height_range_pipeline,
+            operators_pipeline,
+            brush_pipeline,
+            apply_brush_pipeline,
+            flow_maps_pipeline,
         }
     }
 }
@@ -187,21 +331,69 @@ impl FromWorld for HydrologyPipeline {
 enum HydrologyState {
     Loading,
     Init,
+    /// Recompute the normal maps after heights were uploaded from the CPU.
+    Normals,
+    /// Apply the [`HeightOperators`] to the heightmap, then recompute the normal maps.
+    Operators,
+    /// Apply the current [`BrushStroke`], then recompute the normal maps.
+    Brush,
     Update,
 }
 
 struct HydrologyNode {
     state: HydrologyState,
+    rebuild_generation: u32,
+    upload_generation: u32,
+    operators_generation: u32,
+    operators_pending: bool,
+    brush_generation: u32,
 }
 
 impl Default for HydrologyNode {
     fn default() -> Self {
         Self {
             state: HydrologyState::Loading,
+            rebuild_generation: 0,
+            upload_generation: 0,
+            operators_generation: 0,
+            operators_pending: false,
+            brush_generation: 0,
         }
     }
 }
 
+fn write_heights(render_queue: &RenderQueue, heightmap: &GpuImage, heights: &[f32]) {
+    let bytes: Vec<u8> = heights.iter().flat_map(|h| h.to_le_bytes()).collect();
+
+    render_queue.write_texture(
+        heightmap.texture.as_image_copy(),
+        &bytes,
+        ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(heightmap.size.x * 4),
+            rows_per_image: None,
+        },
+        heightmap.texture.size(),
+    );
+}
+
+/// Sets every texel of `image` to zero.
+fn clear_image(render_queue: &RenderQueue, image: &GpuImage) {
+    let texel_size = image.texture_format.block_copy_size(None).unwrap_or(4);
+    let bytes_per_row = image.size.x * texel_size;
+
+    render_queue.write_texture(
+        image.texture.as_image_copy(),
+        &vec![0; (bytes_per_row * image.size.y) as usize],
+        ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(bytes_per_row),
+            rows_per_image: None,
+        },
+        image.texture.size(),
+    );
+}
+
 impl Node for HydrologyNode {
     fn update(&mut self, world: &mut World) {
         let pipeline = world.resource::<HydrologyPipeline>();
@@ -217,13 +409,95 @@ impl Node for HydrologyNode {
                 }
             }
             HydrologyState::Init => {
-                if let CachedPipelineState::Ok(_) =
-                    pipeline_cache.get_compute_pipeline_state(pipeline.update_pipeline)
-                {
+                let loaded = [
+                    pipeline.update_pipeline,
+                    pipeline.normals_pipeline,
+                    pipeline.height_range_pipeline,
+                    pipeline.operators_pipeline,
+                    pipeline.brush_pipeline,
+                    pipeline.apply_brush_pipeline,
+                    pipeline.flow_maps_pipeline,
+                ]
+                .into_iter()
+                .all(|id| {
+                    matches!(
+                        pipeline_cache.get_compute_pipeline_state(id),
+                        CachedPipelineState::Ok(_)
+                    )
+                });
+                if loaded {
                     self.state = HydrologyState::Update;
                 }
             }
-            HydrologyState::Update => {}
+            HydrologyState::Normals | HydrologyState::Operators | HydrologyState::Brush => {
+                self.state = HydrologyState::Update;
+            }
+            HydrologyState::Update => {
+                let rebuild = world.resource::<TerrainRebuild>();
+                if rebuild.generation != self.rebuild_generation {
+                    self.rebuild_generation = rebuild.generation;
+                    if world.resource::<TerrainBuildConfig>().generator == TerrainGenerator::Gpu {
+                        self.state = HydrologyState::Init;
+                    }
+                }
+
+                let upload = world.resource::<HeightmapUpload>();
+                let hydrology_image = world.resource::<HydrologyImage>();
+                let gpu_images = world.resource::<RenderAssets<GpuImage>>();
+                if let (Some(heights), Some(heightmap)) =
+                    (&upload.heights, gpu_images.get(&hydrology_image.heightmap))
+                {
+                    if upload.generation != self.upload_generation {
+                        self.upload_generation = upload.generation;
+                        let render_queue = world.resource::<RenderQueue>();
+                        write_heights(render_queue, heightmap, heights);
+                        // The `init` kernel clears them for terrains built on the GPU.
+                        if upload.new_terrain {
+                            for map in [
+                                &hydrology_image.discharge,
+                                &hydrology_image.momentum,
+                                &hydrology_image.erosion,
+                            ] {
+                                if let Some(map) = gpu_images.get(map) {
+                                    clear_image(render_queue, map);
+                                }
+                            }
+                        }
+                        self.state = HydrologyState::Normals;
+                    }
+                }
+            }
+        }
+
+        // Erosion pauses while the mouse button is held, as a new stroke arrives every frame.
+        let stroke = world.resource::<BrushStroke>();
+        if stroke.generation != self.brush_generation
+            && matches!(self.state, HydrologyState::Update)
+        {
+            self.brush_generation = stroke.generation;
+            // Rain is applied by the `update` kernel itself.
+            if stroke.tool != BrushTool::Rain {
+                self.state = HydrologyState::Brush;
+            }
+        }
+
+        let apply = world.resource::<ApplyHeightOperators>();
+        if apply.generation != self.operators_generation {
+            self.operators_generation = apply.generation;
+            self.operators_pending = true;
+        }
+
+        // Wait for a rebuild or upload to finish, so the operators see the new heights.
+        if self.operators_pending
+            && matches!(self.state, HydrologyState::Update | HydrologyState::Normals)
+        {
+            self.operators_pending = false;
+            self.state = HydrologyState::Operators;
+            world.resource::<RenderQueue>().write_buffer(
+                &pipeline.height_range_buffer,
+                0,
+                &height_range_reset(),
+            );
         }
     }
 
@@ -244,6 +518,7 @@ impl Node for HydrologyNode {
 
         pass.set_bind_group(0, uniform_bind_group, &[]);
         pass.set_bind_group(1, texture_bind_group, &[]);
+        pass.set_bind_group(2, &pipeline.scratch_bind_group, &[]);
 
         match self.state {
             HydrologyState::Loading => {}
@@ -254,12 +529,57 @@ impl Node for HydrologyNode {
                 pass.set_pipeline(init_pipeline);
                 pass.dispatch_workgroups(SIZE.0 / WORKGROUP_SIZE, SIZE.1 / WORKGROUP_SIZE, 1);
             }
+            HydrologyState::Normals => {
+                let normals_pipeline = pipeline_cache
+                    .get_compute_pipeline(pipeline.normals_pipeline)
+                    .unwrap();
+                pass.set_pipeline(normals_pipeline);
+                pass.dispatch_workgroups(SIZE.0 / WORKGROUP_SIZE, SIZE.1 / WORKGROUP_SIZE, 1);
+            }
+            HydrologyState::Operators => {
+                // Every dispatch sees the storage writes of the previous one.
+                for id in [
+                    pipeline.height_range_pipeline,
+                    pipeline.operators_pipeline,
+                    pipeline.normals_pipeline,
+                ] {
+                    pass.set_pipeline(pipeline_cache.get_compute_pipeline(id).unwrap());
+                    pass.dispatch_workgroups(SIZE.0 / WORKGROUP_SIZE, SIZE.1 / WORKGROUP_SIZE, 1);
+                }
+            }
+            HydrologyState::Brush => {
+                for id in [
+                    pipeline.brush_pipeline,
+                    pipeline.apply_brush_pipeline,
+                    pipeline.normals_pipeline,
+                ] {
+                    pass.set_pipeline(pipeline_cache.get_compute_pipeline(id).unwrap());
+                    pass.dispatch_workgroups(SIZE.0 / WORKGROUP_SIZE, SIZE.1 / WORKGROUP_SIZE, 1);
+                }
+            }
             HydrologyState::Update => {
+                let stroke = world.resource::<BrushStroke>();
+                if stroke.tool == BrushTool::Rain && !stroke.active {
+                    return Ok(());
+                }
+
                 let update_pipeline = pipeline_cache
                     .get_compute_pipeline(pipeline.update_pipeline)
                     .unwrap();
                 pass.set_pipeline(update_pipeline);
-                pass.dispatch_workgroups(4, 4, 1);
+                // Rows of `DROPLET_ROW` invocations, the kernel skipping the ones past the buffer.
+                let droplet_count = world.resource::<HydrologyConfig>().clamped_droplet_count();
+                pass.dispatch_workgroups(
+                    DROPLET_ROW / WORKGROUP_SIZE,
+                    droplet_count.div_ceil(DROPLET_ROW * WORKGROUP_SIZE),
+                    1,
+                );
+
+                let flow_maps_pipeline = pipeline_cache
+                    .get_compute_pipeline(pipeline.flow_maps_pipeline)
+                    .unwrap();
+                pass.set_pipeline(flow_maps_pipeline);
+                pass.dispatch_workgroups(SIZE.0 / WORKGROUP_SIZE, SIZE.1 / WORKGROUP_SIZE, 1);
             }
         }
         Ok(())
@@ -275,6 +595,11 @@ impl Plugin for HydrologyComputePlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(ExtractResourcePlugin::<HydrologyImage>::default());
         app.add_plugins(ExtractResourcePlugin::<TerrainUniform>::default());
+        app.add_plugins(ExtractResourcePlugin::<HeightmapUpload>::default());
+        app.add_plugins(ExtractResourcePlugin::<HeightOperators>::default());
+        app.add_plugins(ExtractResourcePlugin::<ApplyHeightOperators>::default());
+        app.add_plugins(ExtractResourcePlugin::<BrushStroke>::default());
+        app.add_plugins(ExtractResourcePlugin::<TrajectorySettings>::default());
 
         let render_app = app.sub_app_mut(RenderApp);
         render_app.add_systems(
@@ -295,6 +620,7 @@ impl Plugin for HydrologyComputePlugin {
             (
                 extract_hydrology_config,
                 extract_terrain_config,
+                extract_terrain_rebuild,
                 extract_time,
             ),
         );
@@ -315,6 +641,10 @@ fn extract_terrain_config(mut commands: Commands, config: Extract<Res<TerrainBui
     commands.insert_resource(**config);
 }
 
+fn extract_terrain_rebuild(mut commands: Commands, rebuild: Extract<Res<TerrainRebuild>>) {
+    commands.insert_resource(**rebuild);
+}
+
 fn extract_time(mut commands: Commands, time: Extract<Res<Time>>) {
     commands.insert_resource(**time);
 }
@@ -1,15 +1,18 @@
-use super::TERRAIN_SIZE;
+use super::{uniforms::HydrologyImage, TERRAIN_SIZE};
 use bevy::{
     prelude::*,
     render::{
         render_asset::RenderAssetUsages,
         render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages},
+        storage::ShaderStorageBuffer,
     },
 };
 
 pub fn build_images(
     mut images: ResMut<Assets<Image>>,
-) -> (Handle<Image>, Handle<Image>, Handle<Image>) {
+    trajectories: Handle<ShaderStorageBuffer>,
+    droplets: Handle<ShaderStorageBuffer>,
+) -> HydrologyImage {
     let mut heightmap_image = Image::new_fill(
         Extent3d {
             width: TERRAIN_SIZE.x,
@@ -21,8 +24,11 @@ pub fn build_images(
         TextureFormat::R32Float,
         RenderAssetUsages::RENDER_WORLD,
     );
-    heightmap_image.texture_descriptor.usage =
-        TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
+    // Copied back to the CPU every frame, see `TerrainHeights`.
+    heightmap_image.texture_descriptor.usage = TextureUsages::COPY_DST
+        | TextureUsages::COPY_SRC
+        | TextureUsages::STORAGE_BINDING
+        | TextureUsages::TEXTURE_BINDING;
 
     let mut normalmap_topleft_image = Image::new_fill(
         Extent3d {
@@ -52,9 +58,81 @@ pub fn build_images(
     normalmap_bottomright_image.texture_descriptor.usage =
         TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
 
-    (
-        images.add(heightmap_image),
-        images.add(normalmap_topleft_image),
-        images.add(normalmap_bottomright_image),
-    )
+    // Kept in the main world as well, so the mask can be rewritten before rebuilding the terrain.
+    let mut mask_image = Image::new_fill(
+        Extent3d {
+            width: TERRAIN_SIZE.x,
+            height: TERRAIN_SIZE.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &1.0f32.to_le_bytes(),
+        TextureFormat::R32Float,
+        RenderAssetUsages::MAIN_WORLD | RenderAssetUsages::RENDER_WORLD,
+    );
+    mask_image.texture_descriptor.usage =
+        TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
+
+    // Water flowing through each cell, accumulated by the drops and blended over time.
+    let mut discharge_image = Image::new_fill(
+        Extent3d {
+            width: TERRAIN_SIZE.x,
+            height: TERRAIN_SIZE.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0; 4],
+        TextureFormat::R32Float,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    // Copied back to the CPU every frame, see `TerrainFlow`.
+    discharge_image.texture_descriptor.usage = TextureUsages::COPY_DST
+        | TextureUsages::COPY_SRC
+        | TextureUsages::STORAGE_BINDING
+        | TextureUsages::TEXTURE_BINDING;
+
+    // Average velocity of that water, weighted by volume, in `xy`.
+    let mut momentum_image = Image::new_fill(
+        Extent3d {
+            width: TERRAIN_SIZE.x,
+            height: TERRAIN_SIZE.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0; 4 * 4],
+        TextureFormat::Rgba32Float,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    momentum_image.texture_descriptor.usage =
+        TextureUsages::COPY_DST | TextureUsages::STORAGE_BINDING | TextureUsages::TEXTURE_BINDING;
+
+    // Height removed by erosion, negative where sediment was deposited.
+    let mut erosion_image = Image::new_fill(
+        Extent3d {
+            width: TERRAIN_SIZE.x,
+            height: TERRAIN_SIZE.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[0; 4],
+        TextureFormat::R32Float,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    // Copied back to the CPU every frame, see `TerrainFlow`.
+    erosion_image.texture_descriptor.usage = TextureUsages::COPY_DST
+        | TextureUsages::COPY_SRC
+        | TextureUsages::STORAGE_BINDING
+        | TextureUsages::TEXTURE_BINDING;
+
+    HydrologyImage {
+        heightmap: images.add(heightmap_image),
+        normalmap_topleft: images.add(normalmap_topleft_image),
+        normalmap_bottomright: images.add(normalmap_bottomright_image),
+        mask: images.add(mask_image),
+        discharge: images.add(discharge_image),
+        momentum: images.add(momentum_image),
+        erosion: images.add(erosion_image),
+        trajectories,
+        droplets,
+    }
 }
@@ -0,0 +1,124 @@
+use std::{error::Error, fmt};
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    prelude::*,
+};
+use serde::Deserialize;
+
+use super::{
+    hydrology_compute::HydrologyConfig, presets::PresetConfig, TerrainBuildConfig, TerrainRebuild,
+};
+
+/// Parameters applied to the running simulation whenever the file changes on disk.
+///
+/// Both sections are optional. Fields missing from a section are reset to their defaults.
+#[derive(Asset, TypePath, Deserialize)]
+pub struct LiveConfig {
+    #[serde(default)]
+    pub terrain: Option<TerrainBuildConfig>,
+    #[serde(default)]
+    pub hydrology: Option<HydrologyConfig>,
+}
+
+#[derive(Debug)]
+pub enum LiveConfigLoaderError {
+    Io(std::io::Error),
+    Ron(ron::error::SpannedError),
+}
+
+impl fmt::Display for LiveConfigLoaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "could not read live config: {error}"),
+            Self::Ron(error) => write!(f, "could not parse live config: {error}"),
+        }
+    }
+}
+
+impl Error for LiveConfigLoaderError {}
+
+#[derive(Default)]
+pub struct LiveConfigLoader;
+
+impl AssetLoader for LiveConfigLoader {
+    type Asset = LiveConfig;
+    type Settings = ();
+    type Error = LiveConfigLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<LiveConfig, Self::Error> {
+        let mut bytes = Vec::new();
+        reader
+            .read_to_end(&mut bytes)
+            .await
+            .map_err(LiveConfigLoaderError::Io)?;
+
+        ron::de::from_bytes(&bytes).map_err(LiveConfigLoaderError::Ron)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["config.ron"]
+    }
+}
+
+/// The watched [`LiveConfig`] file.
+///
+/// Edits are picked up through asset hot reloading, which needs the `hot_reload` feature.
+/// Without it the file is only applied when watching is enabled.
+#[derive(Resource)]
+pub struct LiveConfigFile {
+    pub path: String,
+    pub handle: Option<Handle<LiveConfig>>,
+}
+
+impl Default for LiveConfigFile {
+    fn default() -> Self {
+        Self {
+            path: "live.config.ron".to_owned(),
+            handle: None,
+        }
+    }
+}
+
+/// Applies the live config when it is loaded or modified, rebuilding the terrain when the
+/// generation parameters changed.
+pub fn apply_live_config(
+    mut events: EventReader<AssetEvent<LiveConfig>>,
+    live_config_file: Res<LiveConfigFile>,
+    live_configs: Res<Assets<LiveConfig>>,
+    mut terrain_config: ResMut<TerrainBuildConfig>,
+    mut hydrology_config: ResMut<HydrologyConfig>,
+    mut rebuild: ResMut<TerrainRebuild>,
+) {
+    let Some(handle) = &live_config_file.handle else {
+        events.clear();
+        return;
+    };
+
+    for event in events.read() {
+        let (AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id }) = event
+        else {
+            continue;
+        };
+        let Some(live_config) = live_configs.get(*id).filter(|_| *id == handle.id()) else {
+            continue;
+        };
+        info!("Applying live config '{}'", live_config_file.path);
+
+        if let Some(hydrology) = &live_config.hydrology {
+            hydrology_config.apply_preset(hydrology);
+        }
+        if let Some(terrain) = live_config
+            .terrain
+            .filter(|terrain| *terrain != *terrain_config)
+        {
+            *terrain_config = terrain;
+            rebuild.generation = rebuild.generation.wrapping_add(1);
+        }
+    }
+}
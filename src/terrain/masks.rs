@@ -0,0 +1,122 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::{uniforms::HydrologyImage, TerrainBuildConfig, TerrainRebuild, TERRAIN_SIZE};
+
+/// Shape multiplied into the initial heightmap.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum TerrainMask {
+    #[default]
+    None,
+    /// Radial falloff towards the edges, producing an island.
+    Island,
+    /// Falloff based on the distance to the nearest edge.
+    Square,
+    /// Luminance of [`TerrainMaskImage`], stretched over the whole grid.
+    Image,
+}
+
+/// The image used by [`TerrainMask::Image`].
+#[derive(Resource, Default)]
+pub struct TerrainMaskImage {
+    pub path: String,
+    pub image: Option<Handle<Image>>,
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0).max(f32::EPSILON)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Evaluates a mask for a single cell.
+pub fn mask_value(
+    mask: TerrainMask,
+    radius: f32,
+    falloff: f32,
+    cell: UVec2,
+    image: Option<&Image>,
+) -> f32 {
+    // Distance from the center in [-1, 1] on both axes.
+    let p = (cell.as_vec2() + 0.5) / TERRAIN_SIZE.as_vec2() * 2.0 - 1.0;
+    let falloff = |distance: f32| 1.0 - smoothstep(radius, radius + falloff, distance);
+
+    match mask {
+        TerrainMask::None => 1.0,
+        TerrainMask::Island => falloff(p.length()),
+        TerrainMask::Square => falloff(p.abs().max_element()),
+        TerrainMask::Image => image.map_or(1.0, |image| sample_luminance(image, cell)),
+    }
+}
+
+/// Computes the mask value of every cell, in the same layout as the heightmap.
+pub fn build_mask(config: &TerrainBuildConfig, image: Option<&Image>) -> Vec<f32> {
+    (0..TERRAIN_SIZE.y)
+        .flat_map(|y| (0..TERRAIN_SIZE.x).map(move |x| UVec2::new(x, y)))
+        .map(|cell| {
+            mask_value(
+                config.mask,
+                config.mask_radius,
+                config.mask_falloff,
+                cell,
+                image,
+            )
+        })
+        .collect()
+}
+
+/// Samples the luminance of `image` stretched over the terrain grid.
+pub fn sample_luminance(image: &Image, cell: UVec2) -> f32 {
+    let texel = (cell * image.size() / TERRAIN_SIZE).min(image.size() - 1);
+
+    image
+        .get_color_at(texel.x, texel.y)
+        .map_or(1.0, |color| color.luminance())
+}
+
+/// Applies the mask and the sea level cutoff to a generated height.
+pub fn apply_mask(height: f32, mask: f32, config: &TerrainBuildConfig) -> f32 {
+    let height = height * mask;
+
+    if config.sea_cutoff {
+        height.max(config.sea_level)
+    } else {
+        height
+    }
+}
+
+/// Writes the mask into the `mask` texture read by the `init` compute shader, once per rebuild.
+pub fn update_mask(
+    config: Res<TerrainBuildConfig>,
+    rebuild: Res<TerrainRebuild>,
+    mask_image: Res<TerrainMaskImage>,
+    hydrology_image: Res<HydrologyImage>,
+    mut images: ResMut<Assets<Image>>,
+    mut written_generation: Local<Option<u32>>,
+) {
+    if *written_generation == Some(rebuild.generation) {
+        return;
+    }
+    *written_generation = Some(rebuild.generation);
+
+    let source = mask_image
+        .image
+        .as_ref()
+        .and_then(|handle| images.get(handle));
+    if config.mask == TerrainMask::Image && source.is_none() {
+        warn!("Mask image '{}' is not loaded yet", mask_image.path);
+    }
+
+    let data: Vec<u8> = build_mask(&config, source)
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect();
+    // Borrowing the image mutably uploads it again, so the same mask is not written twice.
+    if images
+        .get(&hydrology_image.mask)
+        .is_some_and(|image| image.data != data)
+    {
+        if let Some(image) = images.get_mut(&hydrology_image.mask) {
+            image.data = data;
+        }
+    }
+}
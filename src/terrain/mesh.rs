@@ -1,51 +1,124 @@
 use super::{
-    hydrology_compute::HydrologyConfig, images::build_images, uniforms::HydrologyImage, CELL_SIZE,
-    TERRAIN_SIZE, TERRAIN_SIZE_F32,
+    chunks::spawn_chunks,
+    droplets::build_droplet_buffer,
+    graph::TerrainGraphSource,
+    heights::{read_discharge, read_erosion, read_heights},
+    hydrology_compute::HydrologyConfig,
+    images::build_images,
+    masks::{TerrainMask, TerrainMaskImage},
+    rendering::TerrainRenderSettings,
+    splat::build_splat_textures,
+    trajectories::build_trajectory_buffer,
+    uniforms::{HeightmapUpload, TerrainRenderUniform},
 };
 use bevy::{
     pbr::{ExtendedMaterial, MaterialExtension, OpaqueRendererMethod},
     prelude::*,
     render::{
-        mesh,
-        render_asset::RenderAssetUsages,
-        render_resource::{AsBindGroup, PrimitiveTopology, ShaderRef},
+        gpu_readback::Readback,
+        render_resource::{AsBindGroup, ShaderRef},
+        storage::ShaderStorageBuffer,
     },
 };
+use serde::{Deserialize, Serialize};
+
+/// Where the initial heightmap is generated.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum TerrainGenerator {
+    /// Simplex noise evaluated by the `init` compute shader.
+    #[default]
+    Gpu,
+    /// Noise graph built from `noise` crate functions, uploaded to the heightmap texture.
+    Cpu,
+    /// A [`super::graph::TerrainGraph`] loaded from disk, evaluated on the CPU.
+    Graph,
+}
 
-type MeshDataResult = (usize, Vec<[f32; 3]>, Vec<[f32; 2]>, Vec<u32>);
-
-#[derive(Resource, Clone, Copy)]
+/// Missing fields are taken from the defaults when deserializing, so presets can be partial.
+#[derive(Resource, Clone, Copy, PartialEq, TypePath, Serialize, Deserialize)]
+#[serde(default)]
 pub struct TerrainBuildConfig {
+    pub generator: TerrainGenerator,
     pub seed: i32,
     pub base_amplitude: f32,
     pub base_frequency: f32,
+    /// Number of domain warping passes (0 disables warping, at most 2).
+    pub warp_levels: u32,
+    /// Offset in cells applied to the sampling coordinates by each warping pass.
+    pub warp_strength: f32,
+    pub warp_frequency: f32,
+    pub mask: TerrainMask,
+    /// Normalized distance from the center where the mask starts to fall off.
+    pub mask_radius: f32,
+    /// Normalized width of the falloff, after which the mask reaches zero.
+    pub mask_falloff: f32,
+    /// Raise everything below `sea_level` to it, giving a flat sea floor.
+    pub sea_cutoff: bool,
+    pub sea_level: f32,
 }
 
 impl Default for TerrainBuildConfig {
     fn default() -> Self {
         Self {
+            generator: TerrainGenerator::default(),
             seed: 96,
             base_amplitude: 20.0,
             base_frequency: 0.01,
+            warp_levels: 0,
+            warp_strength: 40.0,
+            warp_frequency: 0.005,
+            mask: TerrainMask::None,
+            mask_radius: 0.5,
+            mask_falloff: 0.4,
+            sea_cutoff: false,
+            sea_level: 5.0,
         }
     }
 }
 
+/// Bumped to request that the initial terrain is generated again.
+#[derive(Resource, Clone, Copy, Default, PartialEq)]
+pub struct TerrainRebuild {
+    pub generation: u32,
+}
+
 #[derive(Asset, AsBindGroup, Reflect, Debug, Clone)]
 pub struct TerrainShaderExtension {
-    #[texture(100, visibility(vertex))]
-    #[sampler(101, visibility(vertex))]
+    #[texture(100, visibility(vertex, fragment))]
+    #[sampler(101, visibility(vertex, fragment))]
     heightmap: Handle<Image>,
 
-    #[texture(102, visibility(vertex))]
-    #[sampler(103, visibility(vertex))]
+    #[texture(102, visibility(vertex, fragment))]
+    #[sampler(103, visibility(vertex, fragment))]
     normalmap_topleft: Handle<Image>,
 
-    #[texture(104, visibility(vertex))]
-    #[sampler(105, visibility(vertex))]
+    #[texture(104, visibility(vertex, fragment))]
+    #[sampler(105, visibility(vertex, fragment))]
     normalmap_bottomright: Handle<Image>,
+
+    #[texture(106)]
+    #[sampler(107)]
+    discharge: Handle<Image>,
+
+    #[texture(108)]
+    #[sampler(109)]
+    momentum: Handle<Image>,
+
+    #[uniform(110)]
+    pub settings: TerrainRenderUniform,
+
+    #[texture(111)]
+    #[sampler(112)]
+    erosion: Handle<Image>,
+
+    /// Detail of each [`super::splat::SplatLayer`], see [`build_splat_textures`].
+    #[texture(113, dimension = "2d_array")]
+    #[sampler(114)]
+    splat_textures: Handle<Image>,
 }
 
+pub type TerrainMaterial = ExtendedMaterial<StandardMaterial, TerrainShaderExtension>;
+
 impl MaterialExtension for TerrainShaderExtension {
     fn vertex_shader() -> ShaderRef {
         "shaders/terrain_material.wgsl".into()
@@ -65,106 +138,59 @@ impl MaterialExtension for TerrainShaderExtension {
 }
 
 pub fn setup_low_poly_terrain(
-    commands: Commands,
-    meshes: ResMut<Assets<Mesh>>,
-    images: ResMut<Assets<Image>>,
-    materials: ResMut<Assets<ExtendedMaterial<StandardMaterial, TerrainShaderExtension>>>,
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut images: ResMut<Assets<Image>>,
+    mut buffers: ResMut<Assets<ShaderStorageBuffer>>,
+    mut materials: ResMut<Assets<TerrainMaterial>>,
 ) {
-    let mut mesh = Mesh::new(
-        PrimitiveTopology::TriangleList,
-        RenderAssetUsages::MAIN_WORLD | RenderAssetUsages::RENDER_WORLD,
-    );
-    update_mesh(&mut mesh);
-    spawn_mesh(commands, meshes, images, mesh, materials);
-}
-
-fn update_mesh(mesh: &mut Mesh) {
-    let (_, positions, tex_coords, indices) = build_mesh_data();
-
-    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
-    mesh.insert_attribute(Mesh::ATTRIBUTE_UV_0, tex_coords);
-    mesh.insert_indices(mesh::Indices::U32(indices));
-}
-
-fn spawn_mesh(
-    mut commands: Commands<'_, '_>,
-    mut meshes: ResMut<'_, Assets<Mesh>>,
-    images: ResMut<Assets<Image>>,
-    mesh: Mesh,
-    mut materials: ResMut<Assets<ExtendedMaterial<StandardMaterial, TerrainShaderExtension>>>,
-) {
-    let (heightmap, normalmap_topleft, normalmap_bottomright) = build_images(images);
-
-    commands.spawn((
-        Mesh3d(meshes.add(mesh)),
-        MeshMaterial3d(materials.add(ExtendedMaterial {
-            base: StandardMaterial {
-                base_color: Color::linear_rgb(0.3, 0.5, 0.3),
-                metallic: 0.2,
-                perceptual_roughness: 1.0,
-                opaque_render_method: OpaqueRendererMethod::Auto,
-                ..Default::default()
-            },
-            extension: TerrainShaderExtension {
-                heightmap: heightmap.clone(),
-                normalmap_topleft: normalmap_topleft.clone(),
-                normalmap_bottomright: normalmap_bottomright.clone(),
-            },
-        })),
+    let splat_textures = images.add(build_splat_textures());
+    let trajectories = buffers.add(build_trajectory_buffer());
+    let droplets = buffers.add(build_droplet_buffer(
+        HydrologyConfig::default().clamped_droplet_count(),
     ));
-
-    commands.insert_resource(HydrologyImage {
-        heightmap,
-        normalmap_topleft,
-        normalmap_bottomright,
+    let hydrology_image = build_images(images, trajectories, droplets);
+
+    let material = materials.add(ExtendedMaterial {
+        base: StandardMaterial {
+            base_color: Color::linear_rgb(0.3, 0.5, 0.3),
+            metallic: 0.2,
+            perceptual_roughness: 1.0,
+            opaque_render_method: OpaqueRendererMethod::Auto,
+            ..Default::default()
+        },
+        extension: TerrainShaderExtension {
+            heightmap: hydrology_image.heightmap.clone(),
+            normalmap_topleft: hydrology_image.normalmap_topleft.clone(),
+            normalmap_bottomright: hydrology_image.normalmap_bottomright.clone(),
+            discharge: hydrology_image.discharge.clone(),
+            momentum: hydrology_image.momentum.clone(),
+            settings: TerrainRenderSettings::default().uniform(),
+            erosion: hydrology_image.erosion.clone(),
+            splat_textures,
+        },
     });
+    spawn_chunks(&mut commands, &mut meshes, material);
 
-    commands.insert_resource(TerrainBuildConfig::default());
-    commands.insert_resource(HydrologyConfig::default());
-}
+    commands
+        .spawn(Readback::texture(hydrology_image.heightmap.clone()))
+        .observe(read_heights);
+    commands
+        .spawn(Readback::texture(hydrology_image.discharge.clone()))
+        .observe(read_discharge);
+    commands
+        .spawn(Readback::texture(hydrology_image.erosion.clone()))
+        .observe(read_erosion);
 
-fn build_mesh_data() -> MeshDataResult {
-    let cell_count = usize::try_from(TERRAIN_SIZE.x * TERRAIN_SIZE.y).unwrap();
-    let triangle_count = cell_count * 6;
-
-    let mut positions = vec![[0., 0., 0.]; triangle_count];
-    let mut tex_coords = vec![[0., 0.]; triangle_count];
-    let mut indices = vec![0; triangle_count];
-
-    for x in 0..TERRAIN_SIZE.x {
-        for y in 0..TERRAIN_SIZE.y {
-            let x_pos = (x as f32) * CELL_SIZE - TERRAIN_SIZE_F32.x / 2.0;
-            let z_pos = (y as f32) * CELL_SIZE - TERRAIN_SIZE_F32.y / 2.0;
-
-            let i_32 = x + y * TERRAIN_SIZE.x;
-            let i: usize = i_32 as usize;
-
-            positions[i * 6] = [x_pos, 0.0, z_pos];
-            positions[i * 6 + 1] = [x_pos, 0.0, z_pos + CELL_SIZE];
-            positions[i * 6 + 2] = [x_pos + CELL_SIZE, 0.0, z_pos];
-            positions[i * 6 + 3] = [x_pos + CELL_SIZE, 0.0, z_pos + CELL_SIZE];
-            positions[i * 6 + 4] = [x_pos + CELL_SIZE, 0.0, z_pos];
-            positions[i * 6 + 5] = [x_pos, 0.0, z_pos + CELL_SIZE];
-
-            tex_coords[i * 6] = [0.0, 0.0];
-            tex_coords[i * 6 + 1] = [1.0, 0.0];
-            tex_coords[i * 6 + 2] = [2.0, 0.0];
-            tex_coords[i * 6 + 3] = [3.0, 0.0];
-            tex_coords[i * 6 + 4] = [4.0, 0.0];
-            tex_coords[i * 6 + 5] = [5.0, 0.0];
-
-            let slice = &[
-                i_32 * 6,
-                i_32 * 6 + 1,
-                i_32 * 6 + 2,
-                i_32 * 6 + 3,
-                i_32 * 6 + 4,
-                i_32 * 6 + 5,
-            ];
-            let i_idx_usize = i * 6;
-            indices.splice(i_idx_usize..i_idx_usize + 6, slice.iter().cloned());
-        }
-    }
+    commands.insert_resource(hydrology_image);
 
-    (triangle_count, positions, tex_coords, indices)
+    commands.insert_resource(TerrainBuildConfig::default());
+    commands.insert_resource(TerrainRebuild::default());
+    commands.insert_resource(HeightmapUpload::default());
+    commands.insert_resource(TerrainMaskImage::default());
+    commands.insert_resource(TerrainGraphSource {
+        path: "graphs/island.terrain.ron".to_owned(),
+        graph: None,
+    });
+    commands.insert_resource(HydrologyConfig::default());
 }
@@ -0,0 +1,222 @@
+use bevy::prelude::*;
+use bevy_egui::{
+    egui::{self, Color32, ColorImage, Pos2, Sense, Stroke, TextureHandle, TextureOptions},
+    EguiContexts,
+};
+use bevy_panorbit_camera::PanOrbitCamera;
+
+use super::{
+    brush::world_to_cell,
+    heights::{TerrainFlow, TerrainHeights},
+    operators::height_range,
+    TERRAIN_SIZE, TERRAIN_SIZE_F32,
+};
+
+/// Side of the minimap in the UI, in points.
+const MINIMAP_SIZE: f32 = 256.0;
+/// Length of the footprint edges that do not reach the ground, e.g. above the horizon.
+const FOOTPRINT_MAX_DISTANCE: f32 = 1000.0;
+/// Shortest time between two updates of the minimap texture, as the maps change every frame
+/// during erosion.
+const MINIMAP_REFRESH_SECONDS: f32 = 0.25;
+
+/// Map shown in the minimap.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum MinimapLayer {
+    /// Heights from black to white over the range of the heightmap.
+    #[default]
+    Height,
+    Discharge,
+    /// Height removed by erosion in red, sediment deposited in blue.
+    Erosion,
+}
+
+impl MinimapLayer {
+    pub const ALL: [Self; 3] = [Self::Height, Self::Discharge, Self::Erosion];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Height => "Height",
+            Self::Discharge => "Discharge",
+            Self::Erosion => "Erosion",
+        }
+    }
+}
+
+/// Top-down preview of the terrain maps, kept as an egui texture.
+#[derive(Resource, Default)]
+pub struct Minimap {
+    pub layer: MinimapLayer,
+    texture: Option<TextureHandle>,
+    /// Layer currently in the texture.
+    shown_layer: Option<MinimapLayer>,
+    /// Whether the maps changed since the texture was built.
+    stale: bool,
+    /// Seconds since the texture was built.
+    since_refresh: f32,
+}
+
+fn build_minimap_image(
+    layer: MinimapLayer,
+    heights: &TerrainHeights,
+    flow: &TerrainFlow,
+) -> ColorImage {
+    let pixels: Vec<Color32> = match layer {
+        MinimapLayer::Height => {
+            let (min, max) = height_range(&heights.heights);
+            let range = (max - min).max(f32::EPSILON);
+            heights
+                .heights
+                .iter()
+                .map(|height| {
+                    let value = ((height - min) / range * 255.0) as u8;
+                    Color32::from_gray(value)
+                })
+                .collect()
+        }
+        MinimapLayer::Discharge => flow
+            .discharge
+            .iter()
+            .map(|discharge| {
+                let value = 1.0 - (-discharge).exp();
+                Color32::from_rgb(
+                    (value * 80.0) as u8,
+                    (value * 160.0) as u8,
+                    (value * 255.0) as u8,
+                )
+            })
+            .collect(),
+        MinimapLayer::Erosion => flow
+            .erosion
+            .iter()
+            .map(|erosion| {
+                let value = (erosion.abs() * 255.0).min(255.0) as u8;
+                if *erosion > 0.0 {
+                    Color32::from_rgb(255, 255 - value, 255 - value)
+                } else {
+                    Color32::from_rgb(255 - value, 255 - value, 255)
+                }
+            })
+            .collect(),
+    };
+
+    ColorImage {
+        size: [TERRAIN_SIZE.x as usize, TERRAIN_SIZE.y as usize],
+        pixels,
+    }
+}
+
+/// Points of the terrain seen at the corners of the viewport, on the plane of the orbit focus.
+fn camera_footprint(
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+    focus_height: f32,
+) -> Option<[Vec3; 4]> {
+    let size = camera.logical_viewport_size()?;
+    let corners = [
+        Vec2::ZERO,
+        Vec2::new(size.x, 0.0),
+        size,
+        Vec2::new(0.0, size.y),
+    ];
+
+    let mut footprint = [Vec3::ZERO; 4];
+    for (point, corner) in footprint.iter_mut().zip(corners) {
+        let ray = camera.viewport_to_world(camera_transform, corner).ok()?;
+        let distance = ray
+            .intersect_plane(
+                Vec3::new(0.0, focus_height, 0.0),
+                InfinitePlane3d::new(Vec3::Y),
+            )
+            .unwrap_or(FOOTPRINT_MAX_DISTANCE)
+            .min(FOOTPRINT_MAX_DISTANCE);
+        *point = ray.get_point(distance);
+    }
+    Some(footprint)
+}
+
+/// Shows the selected map with the part of the terrain seen by the camera. Clicking or dragging
+/// on the map moves the camera focus there.
+pub fn minimap_ui_system(
+    mut minimap: ResMut<Minimap>,
+    heights: Res<TerrainHeights>,
+    flow: Res<TerrainFlow>,
+    time: Res<Time>,
+    mut cameras: Query<(&Camera, &GlobalTransform, &mut PanOrbitCamera)>,
+    mut contexts: EguiContexts,
+) {
+    let ctx = contexts.ctx_mut();
+
+    let source_changed = match minimap.layer {
+        MinimapLayer::Height => heights.is_changed(),
+        MinimapLayer::Discharge | MinimapLayer::Erosion => flow.is_changed(),
+    };
+    minimap.stale |= source_changed;
+    minimap.since_refresh += time.delta_secs();
+
+    let refresh_due = minimap.stale && minimap.since_refresh >= MINIMAP_REFRESH_SECONDS;
+    if refresh_due || minimap.shown_layer != Some(minimap.layer) {
+        let image = build_minimap_image(minimap.layer, &heights, &flow);
+        match &mut minimap.texture {
+            Some(texture) => texture.set(image, TextureOptions::LINEAR),
+            None => {
+                minimap.texture = Some(ctx.load_texture("minimap", image, TextureOptions::LINEAR))
+            }
+        }
+        minimap.shown_layer = Some(minimap.layer);
+        minimap.stale = false;
+        minimap.since_refresh = 0.0;
+    }
+
+    egui::Window::new("Minimap")
+        .current_pos(Pos2 { x: 1000., y: 10. })
+        .show(ctx, |ui| {
+            egui::ComboBox::from_id_salt("minimap_layer")
+                .selected_text(minimap.layer.label())
+                .show_ui(ui, |ui| {
+                    for layer in MinimapLayer::ALL {
+                        ui.selectable_value(&mut minimap.layer, layer, layer.label());
+                    }
+                });
+
+            let Some(texture) = &minimap.texture else {
+                return;
+            };
+            let (response, painter) =
+                ui.allocate_painter(egui::Vec2::splat(MINIMAP_SIZE), Sense::click_and_drag());
+            let rect = response.rect;
+            painter.image(
+                texture.id(),
+                rect,
+                egui::Rect::from_min_max(Pos2::ZERO, Pos2::new(1.0, 1.0)),
+                Color32::WHITE,
+            );
+
+            // Rows of the maps go along +z, which is down on the minimap.
+            let to_map = |position: Vec3| {
+                let uv = world_to_cell(position) / TERRAIN_SIZE_F32;
+                rect.lerp_inside(egui::Vec2::new(uv.x, uv.y))
+            };
+
+            let Some((camera, camera_transform, mut orbit)) = cameras.iter_mut().next() else {
+                return;
+            };
+            if let Some(footprint) = camera_footprint(camera, camera_transform, orbit.focus.y) {
+                painter.with_clip_rect(rect).add(egui::Shape::closed_line(
+                    footprint.map(to_map).to_vec(),
+                    Stroke::new(1.5, Color32::YELLOW),
+                ));
+            }
+            painter.circle_filled(to_map(orbit.focus), 3.0, Color32::YELLOW);
+
+            if response.clicked() || response.dragged() {
+                if let Some(pointer) = response.interact_pointer_pos() {
+                    let uv = (pointer - rect.min) / rect.size();
+                    let cell =
+                        Vec2::new(uv.x, uv.y).clamp(Vec2::ZERO, Vec2::ONE) * TERRAIN_SIZE_F32;
+                    let position = cell - TERRAIN_SIZE_F32 / 2.0;
+                    orbit.target_focus = Vec3::new(position.x, heights.sample(cell), position.y);
+                }
+            }
+        });
+}
@@ -1,13 +1,58 @@
 mod mesh;
-use mesh::{setup_low_poly_terrain, TerrainBuildConfig, TerrainShaderExtension};
+use mesh::{
+    setup_low_poly_terrain, TerrainBuildConfig, TerrainGenerator, TerrainMaterial, TerrainRebuild,
+};
+mod brush;
+mod chunks;
+mod cpu_erosion;
+mod droplets;
+mod export;
+mod generator;
+mod graph;
+mod heights;
+mod history;
 mod hydrology_compute;
 mod images;
+mod live_config;
+mod masks;
+mod minimap;
+mod operators;
+mod presets;
+mod project;
+mod rendering;
+mod splat;
+mod trajectories;
 mod ui;
 mod uniforms;
 
-use bevy::{pbr::ExtendedMaterial, prelude::*};
+use bevy::prelude::*;
 
-use self::{hydrology_compute::HydrologyComputePlugin, ui::ui_system};
+use self::{
+    brush::{sculpt_terrain, update_orbit_modifier, BrushCursor, BrushSettings, BrushStroke},
+    chunks::{select_chunk_lod, update_chunk_bounds, TerrainLod},
+    droplets::DropletParticlesPlugin,
+    export::{export_mesh, MeshExport},
+    generator::generate_cpu_terrain,
+    graph::{TerrainGraph, TerrainGraphLoader},
+    heights::{TerrainFlow, TerrainHeights},
+    history::{history_shortcuts, record_history, TerrainHistory},
+    hydrology_compute::{HydrologyComputePlugin, HydrologyConfig},
+    live_config::{apply_live_config, LiveConfig, LiveConfigFile, LiveConfigLoader},
+    masks::update_mask,
+    minimap::{minimap_ui_system, Minimap},
+    operators::{apply_operators_on_rebuild, ApplyHeightOperators, HeightOperators},
+    presets::PresetPlugin,
+    project::{handle_project_file, ProjectFile},
+    rendering::{apply_map_camera, update_terrain_material, MapCamera, TerrainRenderSettings},
+    splat::{export_splat_map, SplatExport},
+    trajectories::{
+        draw_trajectories, toggle_trajectory_readback, DropletTrajectories, TrajectorySettings,
+    },
+    ui::{history_ui_system, project_ui_system, rendering_ui_system, sculpt_ui_system, ui_system},
+};
+
+pub(crate) use ui::color_ui;
+pub(crate) use uniforms::HydrologyImage;
 
 pub const TERRAIN_SIZE: bevy::prelude::UVec2 = UVec2::new(256, 256);
 pub const TERRAIN_SIZE_F32: bevy::prelude::Vec2 =
@@ -21,11 +66,65 @@ pub struct LowPolyTerrainPlugin;
 
 impl Plugin for LowPolyTerrainPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(MaterialPlugin::<
-            ExtendedMaterial<StandardMaterial, TerrainShaderExtension>,
-        >::default())
+        app.add_plugins(MaterialPlugin::<TerrainMaterial>::default())
+            .add_plugins(DropletParticlesPlugin)
             .add_plugins(HydrologyComputePlugin)
+            .add_plugins((
+                PresetPlugin::<TerrainBuildConfig>::default(),
+                PresetPlugin::<HydrologyConfig>::default(),
+            ))
+            .init_asset::<TerrainGraph>()
+            .init_asset_loader::<TerrainGraphLoader>()
+            .init_asset::<LiveConfig>()
+            .init_asset_loader::<LiveConfigLoader>()
+            .init_resource::<HeightOperators>()
+            .init_resource::<ApplyHeightOperators>()
+            .init_resource::<TerrainHeights>()
+            .init_resource::<TerrainFlow>()
+            .init_resource::<BrushSettings>()
+            .init_resource::<BrushStroke>()
+            .init_resource::<BrushCursor>()
+            .init_resource::<TerrainHistory>()
+            .init_resource::<ProjectFile>()
+            .init_resource::<LiveConfigFile>()
+            .init_resource::<MeshExport>()
+            .init_resource::<SplatExport>()
+            .init_resource::<TerrainLod>()
+            .init_resource::<TerrainRenderSettings>()
+            .init_resource::<MapCamera>()
+            .init_resource::<Minimap>()
+            .init_resource::<TrajectorySettings>()
+            .init_resource::<DropletTrajectories>()
             .add_systems(Startup, setup_low_poly_terrain)
-            .add_systems(Update, ui_system);
+            .add_systems(
+                Update,
+                (
+                    ui_system,
+                    sculpt_ui_system,
+                    history_ui_system,
+                    history_shortcuts,
+                    project_ui_system,
+                    (rendering_ui_system, minimap_ui_system),
+                    handle_project_file,
+                    apply_live_config,
+                    export_mesh,
+                    export_splat_map,
+                    update_orbit_modifier,
+                    sculpt_terrain,
+                    update_mask,
+                    generate_cpu_terrain,
+                    apply_operators_on_rebuild,
+                    record_history,
+                    update_chunk_bounds,
+                    apply_map_camera,
+                    select_chunk_lod,
+                    update_terrain_material,
+                )
+                    .chain(),
+            )
+            .add_systems(
+                Update,
+                (toggle_trajectory_readback, draw_trajectories).chain(),
+            );
     }
 }
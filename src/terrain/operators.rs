@@ -0,0 +1,236 @@
+use bevy::{prelude::*, render::extract_resource::ExtractResource};
+use serde::{Deserialize, Serialize};
+
+use super::TerrainRebuild;
+
+/// Number of curve points that fit in the `curve_points` uniform.
+pub const MAX_CURVE_POINTS: usize = 16;
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum CurveInterpolation {
+    #[default]
+    Linear,
+    /// Cubic Hermite spline through the points.
+    Smooth,
+}
+
+/// Remapping of normalized heights, given as `[input, output]` pairs sorted by input.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct HeightCurve {
+    pub points: Vec<[f32; 2]>,
+    #[serde(default)]
+    pub interpolation: CurveInterpolation,
+}
+
+impl Default for HeightCurve {
+    fn default() -> Self {
+        Self {
+            points: vec![[0.0, 0.0], [1.0, 1.0]],
+            interpolation: CurveInterpolation::Linear,
+        }
+    }
+}
+
+impl HeightCurve {
+    /// Evaluates the curve at `t`, clamping to the first and last point.
+    pub fn sample(&self, t: f32) -> f32 {
+        let Some(first) = self.points.first() else {
+            return t;
+        };
+        if t <= first[0] {
+            return first[1];
+        }
+
+        for i in 1..self.points.len() {
+            let ([x0, y0], [x1, y1]) = (self.points[i - 1], self.points[i]);
+            if t <= x1 {
+                let width = (x1 - x0).max(f32::EPSILON);
+                let f = (t - x0) / width;
+                return match self.interpolation {
+                    CurveInterpolation::Linear => y0 + (y1 - y0) * f,
+                    CurveInterpolation::Smooth => hermite(
+                        y0,
+                        y1,
+                        self.tangent(i - 1) * width,
+                        self.tangent(i) * width,
+                        f,
+                    ),
+                };
+            }
+        }
+
+        self.points.last().map_or(t, |last| last[1])
+    }
+
+    /// Slope between the neighbours of point `i`, as used by `curve_tangent` in the shader.
+    fn tangent(&self, i: usize) -> f32 {
+        let previous = self.points[i.saturating_sub(1)];
+        let next = self.points[(i + 1).min(self.points.len() - 1)];
+        (next[1] - previous[1]) / (next[0] - previous[0]).max(f32::EPSILON)
+    }
+}
+
+fn hermite(y0: f32, y1: f32, m0: f32, m1: f32, f: f32) -> f32 {
+    let f2 = f * f;
+    let f3 = f2 * f;
+    (2.0 * f3 - 3.0 * f2 + 1.0) * y0
+        + (f3 - 2.0 * f2 + f) * m0
+        + (-2.0 * f3 + 3.0 * f2) * y1
+        + (f3 - f2) * m1
+}
+
+/// Quantizes a normalized height into `steps` terraces.
+///
+/// A sharpness of 0 leaves the height unchanged, values towards 1 flatten the treads and steepen
+/// the risers between them.
+pub fn terrace(t: f32, steps: u32, sharpness: f32) -> f32 {
+    if steps == 0 {
+        return t;
+    }
+
+    let scaled = t * steps as f32;
+    let base = scaled.floor();
+    let exponent = 1.0 / (1.0 - sharpness.clamp(0.0, 0.99));
+
+    (base + (scaled - base).powf(exponent)) / steps as f32
+}
+
+/// Returns the minimum and maximum of a heightmap.
+pub fn height_range(heights: &[f32]) -> (f32, f32) {
+    heights.iter().fold((f32::MAX, f32::MIN), |(min, max), h| {
+        (min.min(*h), max.max(*h))
+    })
+}
+
+/// Applies `operator` to every height, normalized to [0, 1] over the range of the heightmap.
+pub fn map_normalized(heights: &mut [f32], operator: impl Fn(f32) -> f32) {
+    let (min, max) = height_range(heights);
+    let range = (max - min).max(f32::EPSILON);
+
+    for height in heights {
+        *height = min + operator((*height - min) / range) * range;
+    }
+}
+
+/// Post-processing of the heightmap, applied on the GPU by the `operators` compute pass.
+///
+/// Heights are normalized over the current min and max of the heightmap, then go through the
+/// curve, the terraces and finally the plateau clamp.
+#[derive(Resource, Clone, ExtractResource, Serialize, Deserialize)]
+pub struct HeightOperators {
+    pub curve: HeightCurve,
+    pub terrace_steps: u32,
+    pub terrace_sharpness: f32,
+    /// Normalized heights below this become a flat plateau.
+    pub plateau_min: f32,
+    /// Normalized heights above this become a flat plateau.
+    pub plateau_max: f32,
+    /// Apply the operators after every rebuild, before erosion continues.
+    pub apply_on_rebuild: bool,
+}
+
+impl Default for HeightOperators {
+    fn default() -> Self {
+        Self {
+            curve: HeightCurve::default(),
+            terrace_steps: 0,
+            terrace_sharpness: 0.5,
+            plateau_min: 0.0,
+            plateau_max: 1.0,
+            apply_on_rebuild: false,
+        }
+    }
+}
+
+/// Bumped to apply the [`HeightOperators`] to the current heightmap.
+#[derive(Resource, Clone, Copy, Default, PartialEq, ExtractResource)]
+pub struct ApplyHeightOperators {
+    pub generation: u32,
+}
+
+pub fn apply_operators_on_rebuild(
+    operators: Res<HeightOperators>,
+    rebuild: Res<TerrainRebuild>,
+    mut apply: ResMut<ApplyHeightOperators>,
+    mut seen_generation: Local<Option<u32>>,
+) {
+    if *seen_generation == Some(rebuild.generation) {
+        return;
+    }
+    *seen_generation = Some(rebuild.generation);
+
+    if operators.apply_on_rebuild {
+        apply.generation = apply.generation.wrapping_add(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curve(points: Vec<[f32; 2]>, interpolation: CurveInterpolation) -> HeightCurve {
+        HeightCurve {
+            points,
+            interpolation,
+        }
+    }
+
+    #[test]
+    fn curve_passes_through_its_points() {
+        let points = vec![[0.0, 0.1], [0.3, 0.6], [0.7, 0.4], [1.0, 0.9]];
+        for interpolation in [CurveInterpolation::Linear, CurveInterpolation::Smooth] {
+            let curve = curve(points.clone(), interpolation);
+            for [x, y] in &points {
+                assert!((curve.sample(*x) - y).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn curve_interpolates_linearly_and_clamps() {
+        let curve = curve(vec![[0.2, 0.0], [0.6, 1.0]], CurveInterpolation::Linear);
+        assert!((curve.sample(0.4) - 0.5).abs() < 1e-5);
+        assert_eq!(curve.sample(0.0), 0.0);
+        assert_eq!(curve.sample(1.0), 1.0);
+    }
+
+    #[test]
+    fn empty_curve_is_the_identity() {
+        let curve = curve(vec![], CurveInterpolation::Smooth);
+        assert_eq!(curve.sample(0.25), 0.25);
+    }
+
+    #[test]
+    fn default_curve_is_the_identity() {
+        let curve = HeightCurve::default();
+        for t in [0.0, 0.1, 0.5, 0.9, 1.0] {
+            assert!((curve.sample(t) - t).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn terrace_stays_in_range_and_keeps_step_edges() {
+        for sharpness in [0.0, 0.5, 0.99, 2.0] {
+            let mut previous = 0.0;
+            for i in 0..=100 {
+                let t = i as f32 / 100.0;
+                let value = terrace(t, 5, sharpness);
+                assert!((0.0..=1.0).contains(&value));
+                // Terraces keep heights in order.
+                assert!(value >= previous - 1e-6);
+                previous = value;
+            }
+            assert!((terrace(0.4, 5, sharpness) - 0.4).abs() < 1e-5);
+        }
+        assert_eq!(terrace(0.37, 0, 0.9), 0.37);
+    }
+
+    #[test]
+    fn map_normalized_keeps_the_range() {
+        let mut heights = vec![-2.0, 0.0, 3.0, 8.0];
+        map_normalized(&mut heights, |t| t * t);
+        assert_eq!(heights[0], -2.0);
+        assert_eq!(heights[3], 8.0);
+        assert!((heights[2] - (-2.0 + 0.25 * 10.0)).abs() < 1e-5);
+    }
+}
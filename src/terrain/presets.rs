@@ -0,0 +1,230 @@
+use std::{error::Error, fmt, fs, marker::PhantomData, path::PathBuf};
+
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext, LoadedFolder},
+    ecs::system::SystemParam,
+    prelude::*,
+};
+use ron::ser::PrettyConfig;
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{hydrology_compute::HydrologyConfig, TerrainBuildConfig};
+
+/// Directory the [`AssetServer`] reads from, where presets are saved.
+const ASSET_DIRECTORY: &str = "assets";
+
+/// Parameters that can be stored as [`Preset`] files.
+pub trait PresetConfig: Clone + Serialize + DeserializeOwned + TypePath + Send + Sync {
+    /// Directory below `assets/` holding the presets.
+    const DIRECTORY: &'static str;
+    /// Extension of the preset files, without the leading dot.
+    const EXTENSION: &'static str;
+
+    fn apply_preset(&mut self, preset: &Self) {
+        *self = preset.clone();
+    }
+}
+
+impl PresetConfig for TerrainBuildConfig {
+    const DIRECTORY: &'static str = "presets/terrain";
+    const EXTENSION: &'static str = "terrain.preset.ron";
+}
+
+impl PresetConfig for HydrologyConfig {
+    const DIRECTORY: &'static str = "presets/hydrology";
+    const EXTENSION: &'static str = "hydrology.preset.ron";
+}
+
+/// A named set of parameters, loaded from the files in [`PresetConfig::DIRECTORY`].
+#[derive(Asset, TypePath)]
+pub struct Preset<T: PresetConfig>(pub T);
+
+#[derive(Debug)]
+pub enum PresetError {
+    Io(std::io::Error),
+    Serialize(ron::Error),
+    Parse(ron::error::SpannedError),
+    /// The name would place the preset outside of its directory.
+    InvalidName(String),
+}
+
+impl fmt::Display for PresetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "could not access preset: {error}"),
+            Self::Serialize(error) => write!(f, "could not write preset: {error}"),
+            Self::Parse(error) => write!(f, "could not parse preset: {error}"),
+            Self::InvalidName(name) => write!(
+                f,
+                "invalid preset name '{name}', it cannot contain path separators or '..'"
+            ),
+        }
+    }
+}
+
+impl Error for PresetError {}
+
+/// Whether `name` can be used as a preset file name, without leaving the preset directory.
+fn is_valid_preset_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && !name.contains("..")
+        && !name
+            .chars()
+            .any(|c| matches!(c, '/' | '\\' | ':') || c.is_control())
+}
+
+pub struct PresetLoader<T>(PhantomData<T>);
+
+impl<T> Default for PresetLoader<T> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<T: PresetConfig> AssetLoader for PresetLoader<T> {
+    type Asset = Preset<T>;
+    type Settings = ();
+    type Error = PresetError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn Reader,
+        _settings: &(),
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Preset<T>, Self::Error> {
+        let mut bytes = Vec::new();
+        reader
+            .read_to_end(&mut bytes)
+            .await
+            .map_err(PresetError::Io)?;
+
+        ron::de::from_bytes(&bytes)
+            .map(Preset)
+            .map_err(PresetError::Parse)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &[T::EXTENSION]
+    }
+}
+
+/// The loaded presets of one kind, and the state of their UI.
+#[derive(Resource)]
+pub struct PresetLibrary<T: PresetConfig> {
+    /// Keeps every preset in the directory loaded.
+    _folder: Handle<LoadedFolder>,
+    /// Presets saved since startup.
+    saved: Vec<Handle<Preset<T>>>,
+    pub selected: String,
+    /// Name used by "Save as preset".
+    pub save_name: String,
+    /// Result of the last save, shown in the UI.
+    pub status: String,
+}
+
+impl<T: PresetConfig> FromWorld for PresetLibrary<T> {
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            _folder: world.resource::<AssetServer>().load_folder(T::DIRECTORY),
+            saved: Vec::new(),
+            selected: String::new(),
+            save_name: String::new(),
+            status: String::new(),
+        }
+    }
+}
+
+/// Access to the presets of one kind from a system.
+#[derive(SystemParam)]
+pub struct Presets<'w, T: PresetConfig> {
+    pub library: ResMut<'w, PresetLibrary<T>>,
+    assets: Res<'w, Assets<Preset<T>>>,
+    asset_server: Res<'w, AssetServer>,
+}
+
+impl<T: PresetConfig> Presets<'_, T> {
+    /// Names and values of the loaded presets, sorted by name.
+    pub fn list(&self) -> Vec<(String, &T)> {
+        let suffix = format!(".{}", T::EXTENSION);
+        let mut presets: Vec<_> = self
+            .assets
+            .iter()
+            .filter_map(|(id, preset)| {
+                let path = self.asset_server.get_path(id)?;
+                let file_name = path.path().file_name()?.to_string_lossy();
+                let name = file_name.strip_suffix(&suffix).unwrap_or(&file_name);
+                Some((name.to_owned(), &preset.0))
+            })
+            .collect();
+        presets.sort_by(|(a, _), (b, _)| a.cmp(b));
+        presets
+    }
+
+    /// Writes `config` to `assets/<DIRECTORY>/<name>.<EXTENSION>` and loads it.
+    pub fn save(&mut self, name: &str, config: &T) -> Result<(), PresetError> {
+        if !is_valid_preset_name(name) {
+            return Err(PresetError::InvalidName(name.to_owned()));
+        }
+        let asset_path = format!("{}/{name}.{}", T::DIRECTORY, T::EXTENSION);
+        let file_path = PathBuf::from(ASSET_DIRECTORY).join(&asset_path);
+
+        let text = ron::ser::to_string_pretty(config, PrettyConfig::default())
+            .map_err(PresetError::Serialize)?;
+        fs::create_dir_all(file_path.parent().unwrap()).map_err(PresetError::Io)?;
+        fs::write(&file_path, text).map_err(PresetError::Io)?;
+
+        if self
+            .asset_server
+            .get_handle::<Preset<T>>(&asset_path)
+            .is_some()
+        {
+            self.asset_server.reload(asset_path);
+        } else {
+            let handle = self.asset_server.load(asset_path);
+            self.library.saved.push(handle);
+        }
+        Ok(())
+    }
+}
+
+/// Registers the [`Preset`] asset of one kind of parameters.
+pub struct PresetPlugin<T>(PhantomData<T>);
+
+impl<T> Default for PresetPlugin<T> {
+    fn default() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<T: PresetConfig> Plugin for PresetPlugin<T> {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<Preset<T>>()
+            .register_asset_loader(PresetLoader::<T>::default())
+            .init_resource::<PresetLibrary<T>>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preset_names_stay_in_the_preset_directory() {
+        for name in ["alpine", "Desert canyon", "rivers_v1.2", "wet-lowlands"] {
+            assert!(is_valid_preset_name(name), "{name}");
+        }
+        for name in [
+            "",
+            "..",
+            "../terrain",
+            "a/../../b",
+            "nested/name",
+            "C:name",
+            "back\\slash",
+            ".hidden",
+        ] {
+            assert!(!is_valid_preset_name(name), "{name}");
+        }
+    }
+}
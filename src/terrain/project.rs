@@ -0,0 +1,243 @@
+use std::{error::Error, fmt, fs, path::Path, sync::Arc};
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_panorbit_camera::PanOrbitCamera;
+use ron::ser::PrettyConfig;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    graph::TerrainGraphSource,
+    heights::TerrainHeights,
+    history::TerrainHistory,
+    hydrology_compute::HydrologyConfig,
+    masks::{TerrainMask, TerrainMaskImage},
+    operators::HeightOperators,
+    uniforms::{HeightmapUpload, HydrologyImage},
+    TerrainBuildConfig, TerrainGenerator, TERRAIN_SIZE,
+};
+
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct CameraPose {
+    pub focus: [f32; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+    pub radius: f32,
+}
+
+impl CameraPose {
+    pub fn of(camera: &PanOrbitCamera) -> Self {
+        Self {
+            focus: camera.target_focus.to_array(),
+            yaw: camera.target_yaw,
+            pitch: camera.target_pitch,
+            radius: camera.target_radius,
+        }
+    }
+
+    /// Moves the camera to the pose, smoothly.
+    pub fn apply(&self, camera: &mut PanOrbitCamera) {
+        camera.target_focus = Vec3::from_array(self.focus);
+        camera.target_yaw = self.yaw;
+        camera.target_pitch = self.pitch;
+        camera.target_radius = self.radius;
+    }
+}
+
+/// A saved session, written as RON.
+///
+/// The normal maps are not stored, they are recomputed from the heights after loading.
+#[derive(Serialize, Deserialize)]
+pub struct Project {
+    pub terrain: TerrainBuildConfig,
+    pub hydrology: HydrologyConfig,
+    pub operators: HeightOperators,
+    pub mask_image: String,
+    pub graph: String,
+    pub camera: Option<CameraPose>,
+    pub size: [u32; 2],
+    pub heights: Vec<f32>,
+    pub mask: Vec<f32>,
+}
+
+#[derive(Debug)]
+pub enum ProjectError {
+    Io(std::io::Error),
+    Serialize(ron::Error),
+    Parse(ron::error::SpannedError),
+    /// The project was saved with a different [`TERRAIN_SIZE`].
+    SizeMismatch([u32; 2]),
+}
+
+impl fmt::Display for ProjectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "could not access project file: {error}"),
+            Self::Serialize(error) => write!(f, "could not write project: {error}"),
+            Self::Parse(error) => write!(f, "could not parse project: {error}"),
+            Self::SizeMismatch([x, y]) => write!(
+                f,
+                "project terrain is {x}x{y}, expected {}x{}",
+                TERRAIN_SIZE.x, TERRAIN_SIZE.y
+            ),
+        }
+    }
+}
+
+impl Error for ProjectError {}
+
+impl Project {
+    pub fn save(&self, path: &Path) -> Result<(), ProjectError> {
+        let config = PrettyConfig::default().compact_arrays(true);
+        let text = ron::ser::to_string_pretty(self, config).map_err(ProjectError::Serialize)?;
+        fs::write(path, text).map_err(ProjectError::Io)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, ProjectError> {
+        let bytes = fs::read(path).map_err(ProjectError::Io)?;
+        let project: Self = ron::de::from_bytes(&bytes).map_err(ProjectError::Parse)?;
+
+        let cell_count = (TERRAIN_SIZE.x * TERRAIN_SIZE.y) as usize;
+        if project.size != TERRAIN_SIZE.to_array()
+            || project.heights.len() != cell_count
+            || project.mask.len() != cell_count
+        {
+            return Err(ProjectError::SizeMismatch(project.size));
+        }
+        Ok(project)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ProjectAction {
+    Save,
+    Load,
+}
+
+/// The project file edited in the UI, and the action requested for it.
+#[derive(Resource)]
+pub struct ProjectFile {
+    pub path: String,
+    pub pending: Option<ProjectAction>,
+    /// Result of the last save or load, shown in the UI.
+    pub status: String,
+}
+
+impl Default for ProjectFile {
+    fn default() -> Self {
+        Self {
+            path: "terrain.project.ron".to_owned(),
+            pending: None,
+            status: String::new(),
+        }
+    }
+}
+
+/// Everything stored in a [`Project`].
+#[derive(SystemParam)]
+pub struct Session<'w, 's> {
+    terrain: ResMut<'w, TerrainBuildConfig>,
+    hydrology: ResMut<'w, HydrologyConfig>,
+    operators: ResMut<'w, HeightOperators>,
+    mask_image: ResMut<'w, TerrainMaskImage>,
+    graph_source: ResMut<'w, TerrainGraphSource>,
+    hydrology_image: Res<'w, HydrologyImage>,
+    images: ResMut<'w, Assets<Image>>,
+    heights: ResMut<'w, TerrainHeights>,
+    upload: ResMut<'w, HeightmapUpload>,
+    history: ResMut<'w, TerrainHistory>,
+    cameras: Query<'w, 's, &'static mut PanOrbitCamera>,
+    asset_server: Res<'w, AssetServer>,
+}
+
+impl Session<'_, '_> {
+    fn to_project(&self) -> Project {
+        let mask = self
+            .images
+            .get(&self.hydrology_image.mask)
+            .map(|image| {
+                image
+                    .data
+                    .chunks_exact(4)
+                    .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let camera = self.cameras.iter().next().map(CameraPose::of);
+
+        Project {
+            terrain: *self.terrain,
+            hydrology: *self.hydrology,
+            operators: self.operators.clone(),
+            mask_image: self.mask_image.path.clone(),
+            graph: self.graph_source.path.clone(),
+            camera,
+            size: TERRAIN_SIZE.to_array(),
+            heights: self.heights.heights.clone(),
+            mask,
+        }
+    }
+
+    fn apply(&mut self, project: Project) {
+        *self.terrain = project.terrain;
+        *self.hydrology = project.hydrology;
+        *self.operators = project.operators;
+
+        self.mask_image.path = project.mask_image;
+        if project.terrain.mask == TerrainMask::Image {
+            self.mask_image.image = Some(self.asset_server.load(self.mask_image.path.clone()));
+        }
+        self.graph_source.path = project.graph;
+        if project.terrain.generator == TerrainGenerator::Graph {
+            self.graph_source.graph = Some(self.asset_server.load(self.graph_source.path.clone()));
+        }
+
+        if let Some(image) = self.images.get_mut(&self.hydrology_image.mask) {
+            image.data = project
+                .mask
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect();
+        }
+
+        if let Some(pose) = project.camera {
+            for mut camera in &mut self.cameras {
+                pose.apply(&mut camera);
+            }
+        }
+
+        self.history.push("Load project", &self.heights);
+        self.heights.heights = project.heights;
+        self.upload
+            .push_terrain(Arc::new(self.heights.heights.clone()));
+    }
+}
+
+/// Saves or loads the project requested from the UI.
+pub fn handle_project_file(mut project_file: ResMut<ProjectFile>, mut session: Session) {
+    let Some(action) = project_file.pending.take() else {
+        return;
+    };
+    let path = project_file.path.clone();
+
+    let result = match action {
+        ProjectAction::Save => session
+            .to_project()
+            .save(Path::new(&path))
+            .map(|_| format!("Saved {path}")),
+        ProjectAction::Load => Project::load(Path::new(&path)).map(|project| {
+            session.apply(project);
+            format!("Loaded {path}")
+        }),
+    };
+
+    project_file.status = match result {
+        Ok(status) => {
+            info!("{status}");
+            status
+        }
+        Err(error) => {
+            error!("{error}");
+            error.to_string()
+        }
+    };
+}
@@ -0,0 +1,287 @@
+use std::f32::consts::FRAC_PI_2;
+
+use bevy::{prelude::*, render::camera::ScalingMode};
+use bevy_panorbit_camera::PanOrbitCamera;
+
+use super::{
+    chunks::TerrainChunk, mesh::TerrainMaterial, project::CameraPose, splat::SplatSettings,
+    uniforms::TerrainRenderUniform, TERRAIN_SIZE_F32,
+};
+
+/// Normals used to light the terrain.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum TerrainShading {
+    /// One normal per triangle, for the low poly look.
+    #[default]
+    Flat,
+    /// Normals interpolated between vertices, from the gradient of the heightmap.
+    Smooth,
+}
+
+/// Replaces the shaded terrain with a view of one of its maps, to diagnose parameters.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum TerrainDebugView {
+    #[default]
+    None,
+    /// Color ramp between [`DebugViewSettings::height_min`] and `height_max`.
+    Height,
+    Normals,
+    /// `1 - normal.y`, from green on flat ground to red on cliffs.
+    Slope,
+    /// Laplacian of the heightmap, blue in valleys and red on ridges.
+    Curvature,
+    /// Height removed by erosion in red, sediment deposited in blue.
+    Erosion,
+    /// Drop steps taken per frame in each cell.
+    DropDensity,
+    Discharge,
+    /// Iso-height lines every [`DebugViewSettings::contour_interval`].
+    Contours,
+}
+
+impl TerrainDebugView {
+    pub const ALL: [Self; 9] = [
+        Self::None,
+        Self::Height,
+        Self::Normals,
+        Self::Slope,
+        Self::Curvature,
+        Self::Erosion,
+        Self::DropDensity,
+        Self::Discharge,
+        Self::Contours,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::None => "None",
+            Self::Height => "Height",
+            Self::Normals => "Normals",
+            Self::Slope => "Slope",
+            Self::Curvature => "Curvature",
+            Self::Erosion => "Erosion",
+            Self::DropDensity => "Drop density",
+            Self::Discharge => "Discharge",
+            Self::Contours => "Contour lines",
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct DebugViewSettings {
+    pub view: TerrainDebugView,
+    pub height_min: f32,
+    pub height_max: f32,
+    /// Multiplies the curvature, erosion, drop density and discharge before they are mapped to
+    /// colors.
+    pub scale: f32,
+    pub contour_interval: f32,
+}
+
+impl Default for DebugViewSettings {
+    fn default() -> Self {
+        Self {
+            view: TerrainDebugView::default(),
+            height_min: 0.0,
+            height_max: 50.0,
+            scale: 1.0,
+            contour_interval: 2.0,
+        }
+    }
+}
+
+/// Colors of the hypsometric tint, from the lowest to the highest elevation.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub enum ElevationRamp {
+    /// Greens, tans and browns of printed maps, with white peaks.
+    #[default]
+    Atlas,
+    /// Blue lowlands to white peaks, same as the height debug view.
+    Spectral,
+    Grayscale,
+}
+
+impl ElevationRamp {
+    pub const ALL: [Self; 3] = [Self::Atlas, Self::Spectral, Self::Grayscale];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Atlas => "Atlas",
+            Self::Spectral => "Spectral",
+            Self::Grayscale => "Grayscale",
+        }
+    }
+}
+
+/// Map style overlay of contour lines and elevation colors, drawn over the shaded terrain.
+#[derive(Clone, PartialEq, Debug)]
+pub struct MapOverlaySettings {
+    pub contours: bool,
+    /// Height between two thin contour lines.
+    pub minor_interval: f32,
+    /// Height between two thick contour lines.
+    pub major_interval: f32,
+    /// Color of the lines, its alpha is the opacity of the major lines.
+    pub contour_color: LinearRgba,
+    /// Tint the terrain by elevation.
+    pub hypsometric_tint: bool,
+    pub ramp: ElevationRamp,
+    /// Heights mapped to both ends of the ramp.
+    pub ramp_min: f32,
+    pub ramp_max: f32,
+    /// How much of the tint replaces the terrain color.
+    pub tint_strength: f32,
+}
+
+impl Default for MapOverlaySettings {
+    fn default() -> Self {
+        Self {
+            contours: false,
+            minor_interval: 2.0,
+            major_interval: 10.0,
+            contour_color: LinearRgba::new(0.15, 0.08, 0.02, 0.9),
+            hypsometric_tint: false,
+            ramp: ElevationRamp::default(),
+            ramp_min: 0.0,
+            ramp_max: 50.0,
+            tint_strength: 0.8,
+        }
+    }
+}
+
+/// How the terrain material draws the hydrology maps, edited in the "Rendering" window.
+#[derive(Resource, Clone, PartialEq)]
+pub struct TerrainRenderSettings {
+    pub shading: TerrainShading,
+    /// With smooth shading, compute the normal per fragment from the heightmap instead of
+    /// interpolating it, which keeps the full detail on chunks with fewer vertices.
+    pub normal_mapping: bool,
+    /// Draw water where the discharge map exceeds `river_threshold`.
+    pub show_rivers: bool,
+    pub river_threshold: f32,
+    pub river_color: LinearRgba,
+    /// Speed of the ripples moving along the momentum map.
+    pub river_flow_speed: f32,
+    pub splat: SplatSettings,
+    pub map: MapOverlaySettings,
+    pub debug: DebugViewSettings,
+}
+
+impl Default for TerrainRenderSettings {
+    fn default() -> Self {
+        Self {
+            shading: TerrainShading::default(),
+            normal_mapping: false,
+            show_rivers: true,
+            river_threshold: 0.3,
+            river_color: LinearRgba::new(0.05, 0.2, 0.35, 0.9),
+            river_flow_speed: 4.0,
+            splat: SplatSettings::default(),
+            map: MapOverlaySettings::default(),
+            debug: DebugViewSettings::default(),
+        }
+    }
+}
+
+impl TerrainRenderSettings {
+    pub fn uniform(&self) -> TerrainRenderUniform {
+        TerrainRenderUniform {
+            river_color: self.river_color.to_vec4(),
+            river_threshold: self.river_threshold,
+            river_flow_speed: self.river_flow_speed,
+            show_rivers: self.show_rivers.into(),
+            smooth_shading: (self.shading == TerrainShading::Smooth).into(),
+            normal_mapping: self.normal_mapping.into(),
+            splat_enabled: self.splat.enabled.into(),
+            splat_blend: self.splat.clamped_blend(),
+            splat_texture_scale: self.splat.texture_scale,
+            contour_color: self.map.contour_color.to_vec4(),
+            contours: self.map.contours.into(),
+            contour_minor_interval: self.map.minor_interval,
+            contour_major_interval: self.map.major_interval,
+            hypsometric_tint: self.map.hypsometric_tint.into(),
+            elevation_ramp: self.map.ramp as u32,
+            ramp_min: self.map.ramp_min,
+            ramp_max: self.map.ramp_max,
+            tint_strength: self.map.tint_strength,
+            debug_view: self.debug.view as u32,
+            debug_height_min: self.debug.height_min,
+            debug_height_max: self.debug.height_max,
+            debug_scale: self.debug.scale,
+            debug_contour_interval: self.debug.contour_interval,
+            splat_rules: self.splat.uniform_rules(),
+        }
+    }
+}
+
+/// Copies the settings to the material shared by the terrain chunks when they change.
+pub fn update_terrain_material(
+    settings: Res<TerrainRenderSettings>,
+    chunks: Query<&MeshMaterial3d<TerrainMaterial>, With<TerrainChunk>>,
+    mut materials: ResMut<Assets<TerrainMaterial>>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+
+    let Some(material) = chunks
+        .iter()
+        .next()
+        .and_then(|material| materials.get_mut(material))
+    else {
+        return;
+    };
+    material.extension.settings = settings.uniform();
+}
+
+/// Switches the camera to a top-down orthographic view of the terrain, for reading the map
+/// overlay without perspective.
+#[derive(Resource, Default)]
+pub struct MapCamera {
+    pub top_down: bool,
+    /// Pose of the camera before entering the top-down view, restored when leaving it.
+    saved_pose: Option<CameraPose>,
+}
+
+pub fn apply_map_camera(
+    mut map_camera: ResMut<MapCamera>,
+    mut cameras: Query<(&mut PanOrbitCamera, &mut Projection)>,
+) {
+    if map_camera.top_down == map_camera.saved_pose.is_some() {
+        return;
+    }
+    let Some((mut camera, mut projection)) = cameras.iter_mut().next() else {
+        return;
+    };
+
+    if let Some(pose) = map_camera.saved_pose.take() {
+        *projection = Projection::Perspective(default());
+        pose.apply(&mut camera);
+        camera.yaw_lower_limit = None;
+        camera.yaw_upper_limit = None;
+        camera.pitch_lower_limit = None;
+        camera.pitch_upper_limit = None;
+    } else {
+        map_camera.saved_pose = Some(CameraPose::of(&camera));
+        // The radius of the camera becomes the scale of the projection, which is the height of
+        // the view in world units.
+        *projection = Projection::Orthographic(OrthographicProjection {
+            scaling_mode: ScalingMode::FixedVertical {
+                viewport_height: 1.0,
+            },
+            ..OrthographicProjection::default_3d()
+        });
+        // Looking straight down with north up, only panning and zooming.
+        CameraPose {
+            focus: [camera.target_focus.x, 0.0, camera.target_focus.z],
+            yaw: 0.0,
+            pitch: FRAC_PI_2,
+            radius: TERRAIN_SIZE_F32.y,
+        }
+        .apply(&mut camera);
+        camera.yaw_lower_limit = Some(0.0);
+        camera.yaw_upper_limit = Some(0.0);
+        camera.pitch_lower_limit = Some(FRAC_PI_2);
+        camera.pitch_upper_limit = Some(FRAC_PI_2);
+    }
+}
@@ -0,0 +1,460 @@
+use std::path::PathBuf;
+
+use bevy::{
+    image::{ImageAddressMode, ImageFilterMode, ImageSampler, ImageSamplerDescriptor},
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{
+            Extent3d, TextureDimension, TextureFormat, TextureViewDescriptor, TextureViewDimension,
+        },
+    },
+};
+use image::{ImageFormat, RgbaImage};
+
+use super::{
+    heights::{TerrainFlow, TerrainHeights},
+    rendering::TerrainRenderSettings,
+    uniforms::SplatRuleUniform,
+    TERRAIN_SIZE,
+};
+
+pub const SPLAT_LAYER_COUNT: usize = 5;
+
+/// Width of the transition at the ends of each [`SplatRange`], multiplied by
+/// [`SplatSettings::blend`]. Same as in `terrain_material.wgsl`.
+const ALTITUDE_SOFTNESS: f32 = 2.0;
+const SLOPE_SOFTNESS: f32 = 0.05;
+const MOISTURE_SOFTNESS: f32 = 0.1;
+const EROSION_SOFTNESS: f32 = 0.1;
+/// Smallest [`SplatSettings::blend`], as ranges without any transition have undefined edges.
+pub const MIN_SPLAT_BLEND: f32 = 0.05;
+
+/// Size of each layer of the detail texture.
+const DETAIL_SIZE: u32 = 128;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SplatLayer {
+    Grass,
+    Rock,
+    Sand,
+    Snow,
+    Sediment,
+}
+
+impl SplatLayer {
+    pub const ALL: [Self; SPLAT_LAYER_COUNT] = [
+        Self::Grass,
+        Self::Rock,
+        Self::Sand,
+        Self::Snow,
+        Self::Sediment,
+    ];
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SplatRange {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl SplatRange {
+    pub const ANY: Self = Self::new(-1000.0, 1000.0);
+
+    pub const fn new(min: f32, max: f32) -> Self {
+        Self { min, max }
+    }
+
+    /// 1 inside the range, fading to 0 over `softness` on both ends.
+    fn weight(self, value: f32, softness: f32) -> f32 {
+        smoothstep(self.min - softness, self.min + softness, value)
+            * (1.0 - smoothstep(self.max - softness, self.max + softness, value))
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Where a layer covers the terrain. Its weight is the product of the range weights, scaled by
+/// `strength`, and the layers are normalized against each other.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SplatRule {
+    pub color: LinearRgba,
+    pub strength: f32,
+    /// Height of the surface.
+    pub altitude: SplatRange,
+    /// `1 - normal.y`, 0 on flat ground.
+    pub slope: SplatRange,
+    /// Discharge relative to the river threshold, clamped to 1.
+    pub moisture: SplatRange,
+    /// Height removed by erosion, negative where sediment was deposited.
+    pub erosion: SplatRange,
+}
+
+impl SplatRule {
+    fn weight(&self, sample: &SplatSample, blend: f32) -> f32 {
+        self.strength
+            * self
+                .altitude
+                .weight(sample.altitude, ALTITUDE_SOFTNESS * blend)
+            * self.slope.weight(sample.slope, SLOPE_SOFTNESS * blend)
+            * self
+                .moisture
+                .weight(sample.moisture, MOISTURE_SOFTNESS * blend)
+            * self
+                .erosion
+                .weight(sample.erosion, EROSION_SOFTNESS * blend)
+    }
+}
+
+/// The terrain properties the splat rules are evaluated on.
+pub struct SplatSample {
+    pub altitude: f32,
+    pub slope: f32,
+    pub moisture: f32,
+    pub erosion: f32,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct SplatSettings {
+    /// Texture the terrain with the layers, instead of the flat material color.
+    pub enabled: bool,
+    /// Scales the transitions between layers, used as at least [`MIN_SPLAT_BLEND`].
+    pub blend: f32,
+    /// Repetitions of the detail textures per cell.
+    pub texture_scale: f32,
+    /// One rule per [`SplatLayer`], in order.
+    pub rules: [SplatRule; SPLAT_LAYER_COUNT],
+}
+
+impl Default for SplatSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            blend: 1.0,
+            texture_scale: 0.25,
+            rules: [
+                // Grass
+                SplatRule {
+                    color: LinearRgba::rgb(0.3, 0.5, 0.3),
+                    strength: 0.3,
+                    altitude: SplatRange::ANY,
+                    slope: SplatRange::ANY,
+                    moisture: SplatRange::ANY,
+                    erosion: SplatRange::ANY,
+                },
+                // Rock
+                SplatRule {
+                    color: LinearRgba::rgb(0.35, 0.33, 0.3),
+                    strength: 1.0,
+                    altitude: SplatRange::ANY,
+                    slope: SplatRange::new(0.25, 1000.0),
+                    moisture: SplatRange::ANY,
+                    erosion: SplatRange::ANY,
+                },
+                // Sand
+                SplatRule {
+                    color: LinearRgba::rgb(0.76, 0.7, 0.5),
+                    strength: 1.0,
+                    altitude: SplatRange::new(-1000.0, 7.0),
+                    slope: SplatRange::new(-1000.0, 0.3),
+                    moisture: SplatRange::new(-1000.0, 0.5),
+                    erosion: SplatRange::ANY,
+                },
+                // Snow
+                SplatRule {
+                    color: LinearRgba::rgb(0.95, 0.95, 1.0),
+                    strength: 1.0,
+                    altitude: SplatRange::new(45.0, 1000.0),
+                    slope: SplatRange::new(-1000.0, 0.35),
+                    moisture: SplatRange::ANY,
+                    erosion: SplatRange::ANY,
+                },
+                // Sediment
+                SplatRule {
+                    color: LinearRgba::rgb(0.45, 0.35, 0.25),
+                    strength: 1.0,
+                    altitude: SplatRange::ANY,
+                    slope: SplatRange::new(-1000.0, 0.4),
+                    moisture: SplatRange::ANY,
+                    erosion: SplatRange::new(-1000.0, -0.3),
+                },
+            ],
+        }
+    }
+}
+
+impl SplatSettings {
+    /// [`Self::blend`] as given to the shader.
+    pub fn clamped_blend(&self) -> f32 {
+        self.blend.max(MIN_SPLAT_BLEND)
+    }
+
+    /// Normalized weight of each layer, same as `splat_color` in `terrain_material.wgsl`.
+    pub fn weights(&self, sample: &SplatSample) -> [f32; SPLAT_LAYER_COUNT] {
+        let blend = self.clamped_blend();
+        let mut weights = self.rules.map(|rule| rule.weight(sample, blend));
+        let total: f32 = weights.iter().sum();
+        if total > 1e-4 {
+            weights.iter_mut().for_each(|weight| *weight /= total);
+        }
+        weights
+    }
+
+    pub fn uniform_rules(&self) -> [SplatRuleUniform; SPLAT_LAYER_COUNT] {
+        self.rules.map(|rule| SplatRuleUniform {
+            color: rule.color.to_vec4(),
+            altitude_slope: Vec4::new(
+                rule.altitude.min,
+                rule.altitude.max,
+                rule.slope.min,
+                rule.slope.max,
+            ),
+            moisture_erosion: Vec4::new(
+                rule.moisture.min,
+                rule.moisture.max,
+                rule.erosion.min,
+                rule.erosion.max,
+            ),
+            strength: rule.strength,
+        })
+    }
+}
+
+/// Tileable value noise in `[0, 1]`, with `period` lattice cells across the texture.
+fn tileable_noise(x: u32, y: u32, period: u32, seed: u32) -> f32 {
+    let lattice = |x: u32, y: u32| {
+        let mut state = (x % period) ^ ((y % period) << 16) ^ seed.wrapping_mul(0x9e37_79b9);
+        state ^= state >> 16;
+        state = state.wrapping_mul(0x7feb_352d);
+        state ^= state >> 15;
+        state = state.wrapping_mul(0x846c_a68b);
+        state ^= state >> 16;
+        state as f32 / u32::MAX as f32
+    };
+
+    let cell_size = DETAIL_SIZE as f32 / period as f32;
+    let position = Vec2::new(x as f32, y as f32) / cell_size;
+    let cell = position.floor();
+    let f = position - cell;
+    let f = f * f * (Vec2::splat(3.0) - 2.0 * f);
+    let (cx, cy) = (cell.x as u32, cell.y as u32);
+
+    let top = lattice(cx, cy) + (lattice(cx + 1, cy) - lattice(cx, cy)) * f.x;
+    let bottom = lattice(cx, cy + 1) + (lattice(cx + 1, cy + 1) - lattice(cx, cy + 1)) * f.x;
+    top + (bottom - top) * f.y
+}
+
+/// Grayscale detail of each [`SplatLayer`], around 0.5 so the shader can scale the layer colors
+/// by twice its value.
+pub fn build_splat_textures() -> Image {
+    // (lattice period, amplitude) octaves and contrast of each layer.
+    let layers: [(&[(u32, f32)], f32); SPLAT_LAYER_COUNT] = [
+        (&[(4, 0.5), (16, 0.3), (64, 0.2)], 0.5),
+        (&[(2, 0.4), (8, 0.35), (32, 0.25)], 0.8),
+        (&[(32, 0.5), (64, 0.5)], 0.25),
+        (&[(4, 0.7), (16, 0.3)], 0.15),
+        (&[(8, 0.5), (32, 0.5)], 0.5),
+    ];
+
+    let mut data = Vec::with_capacity((DETAIL_SIZE * DETAIL_SIZE * 4) as usize * SPLAT_LAYER_COUNT);
+    for (layer, (octaves, contrast)) in layers.iter().enumerate() {
+        for y in 0..DETAIL_SIZE {
+            for x in 0..DETAIL_SIZE {
+                let noise: f32 = octaves
+                    .iter()
+                    .map(|&(period, amplitude)| {
+                        amplitude * tileable_noise(x, y, period, layer as u32 * 7 + period)
+                    })
+                    .sum();
+                let value = (0.5 + contrast * (noise - 0.5)).clamp(0.0, 1.0);
+                let byte = (value * 255.0) as u8;
+                data.extend_from_slice(&[byte, byte, byte, 255]);
+            }
+        }
+    }
+
+    let mut image = Image::new(
+        Extent3d {
+            width: DETAIL_SIZE,
+            height: DETAIL_SIZE,
+            depth_or_array_layers: SPLAT_LAYER_COUNT as u32,
+        },
+        TextureDimension::D2,
+        data,
+        TextureFormat::Rgba8Unorm,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    image.texture_view_descriptor = Some(TextureViewDescriptor {
+        dimension: Some(TextureViewDimension::D2Array),
+        ..default()
+    });
+    image.sampler = ImageSampler::Descriptor(ImageSamplerDescriptor {
+        address_mode_u: ImageAddressMode::Repeat,
+        address_mode_v: ImageAddressMode::Repeat,
+        mag_filter: ImageFilterMode::Linear,
+        min_filter: ImageFilterMode::Linear,
+        ..default()
+    });
+    image
+}
+
+/// Settings of the splat map export in the UI.
+#[derive(Resource)]
+pub struct SplatExport {
+    /// Path of the exported images, without the index and extension.
+    pub path: String,
+    pub pending: bool,
+    /// Result of the last export, shown in the UI.
+    pub status: String,
+}
+
+impl Default for SplatExport {
+    fn default() -> Self {
+        Self {
+            path: "terrain_splat".to_owned(),
+            pending: false,
+            status: String::new(),
+        }
+    }
+}
+
+/// `1 - normal.y` of the smooth normal of a cell, from the central differences of its heights.
+fn slope(heights: &[f32], x: u32, y: u32) -> f32 {
+    let max = TERRAIN_SIZE - 1;
+    let at = |x: u32, y: u32| heights[(x + y * TERRAIN_SIZE.x) as usize];
+    let gradient = Vec2::new(
+        (at((x + 1).min(max.x), y) - at(x.saturating_sub(1), y)) / 2.0,
+        (at(x, (y + 1).min(max.y)) - at(x, y.saturating_sub(1))) / 2.0,
+    );
+    1.0 - Vec3::new(-gradient.x, 1.0, -gradient.y).normalize().y
+}
+
+/// Writes the layer weights of every cell as two RGBA images, `<path>_0.png` with grass, rock,
+/// sand and snow and `<path>_1.png` with sediment in red. The first row is the lowest z.
+pub fn export_splat_map(
+    mut export: ResMut<SplatExport>,
+    heights: Res<TerrainHeights>,
+    flow: Res<TerrainFlow>,
+    settings: Res<TerrainRenderSettings>,
+) {
+    if !std::mem::take(&mut export.pending) {
+        return;
+    }
+
+    let mut images = [
+        RgbaImage::new(TERRAIN_SIZE.x, TERRAIN_SIZE.y),
+        RgbaImage::new(TERRAIN_SIZE.x, TERRAIN_SIZE.y),
+    ];
+    for y in 0..TERRAIN_SIZE.y {
+        for x in 0..TERRAIN_SIZE.x {
+            let i = (x + y * TERRAIN_SIZE.x) as usize;
+            let sample = SplatSample {
+                altitude: heights.heights[i],
+                slope: slope(&heights.heights, x, y),
+                moisture: (flow.discharge[i] / settings.river_threshold).min(1.0),
+                erosion: flow.erosion[i],
+            };
+            let weights = settings.splat.weights(&sample).map(|w| (w * 255.0) as u8);
+            images[0].put_pixel(
+                x,
+                y,
+                image::Rgba([weights[0], weights[1], weights[2], weights[3]]),
+            );
+            images[1].put_pixel(x, y, image::Rgba([weights[4], 0, 0, 255]));
+        }
+    }
+
+    let paths = [0, 1].map(|index| PathBuf::from(format!("{}_{index}.png", export.path)));
+    let result = images
+        .iter()
+        .zip(&paths)
+        .try_for_each(|(image, path)| image.save_with_format(path, ImageFormat::Png));
+
+    export.status = match result {
+        Ok(()) => {
+            info!("Exported splat maps to {}_*.png", export.path);
+            format!("Exported {} and {}", paths[0].display(), paths[1].display())
+        }
+        Err(error) => {
+            error!("Could not export splat maps: {error}");
+            format!("Could not export splat maps: {error}")
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(altitude: f32, slope: f32, moisture: f32, erosion: f32) -> SplatSample {
+        SplatSample {
+            altitude,
+            slope,
+            moisture,
+            erosion,
+        }
+    }
+
+    fn dominant(weights: [f32; SPLAT_LAYER_COUNT]) -> SplatLayer {
+        let (index, _) = weights
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .unwrap();
+        SplatLayer::ALL[index]
+    }
+
+    #[test]
+    fn range_weight_fades_at_the_ends() {
+        let range = SplatRange::new(0.0, 1.0);
+        assert_eq!(range.weight(0.5, 0.1), 1.0);
+        assert_eq!(range.weight(-0.2, 0.1), 0.0);
+        assert_eq!(range.weight(1.2, 0.1), 0.0);
+        assert!((range.weight(0.0, 0.1) - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn weights_are_normalized() {
+        let settings = SplatSettings::default();
+        for sample in [
+            sample(0.0, 0.0, 0.0, 0.0),
+            sample(20.0, 0.1, 1.0, 0.2),
+            sample(60.0, 0.6, 0.0, -1.0),
+        ] {
+            let weights = settings.weights(&sample);
+            assert!(weights.iter().all(|weight| (0.0..=1.0).contains(weight)));
+            assert!((weights.iter().sum::<f32>() - 1.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn default_rules_pick_the_expected_layers() {
+        let settings = SplatSettings::default();
+        let weights = |altitude, slope, moisture, erosion| {
+            settings.weights(&sample(altitude, slope, moisture, erosion))
+        };
+        assert_eq!(dominant(weights(2.0, 0.0, 0.0, 0.0)), SplatLayer::Sand);
+        assert_eq!(dominant(weights(20.0, 0.6, 0.0, 0.0)), SplatLayer::Rock);
+        assert_eq!(dominant(weights(60.0, 0.0, 0.0, 0.0)), SplatLayer::Snow);
+        assert_eq!(
+            dominant(weights(20.0, 0.0, 1.0, -1.0)),
+            SplatLayer::Sediment
+        );
+        assert_eq!(dominant(weights(20.0, 0.0, 1.0, 0.0)), SplatLayer::Grass);
+    }
+
+    #[test]
+    fn zero_blend_is_clamped() {
+        let settings = SplatSettings {
+            blend: 0.0,
+            ..default()
+        };
+        // Exactly on the end of the sand altitude and rock slope ranges.
+        let weights = settings.weights(&sample(7.0, 0.25, 0.0, 0.0));
+        assert!(weights.iter().all(|weight| weight.is_finite()));
+        assert!((weights.iter().sum::<f32>() - 1.0).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,154 @@
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::ExtractResource,
+        gpu_readback::{Readback, ReadbackComplete},
+        render_asset::RenderAssetUsages,
+        render_resource::BufferUsages,
+        storage::ShaderStorageBuffer,
+    },
+};
+
+use super::{heights::TerrainHeights, uniforms::HydrologyImage, TERRAIN_SIZE_F32};
+
+/// Droplets recorded by the `update` kernel, the first ones of the droplet buffer. Same as in
+/// `erosion.wgsl`.
+pub const TRAJECTORY_COUNT: usize = 16;
+/// Steps recorded for each droplet since it spawned. Same as in `erosion.wgsl`.
+pub const TRAJECTORY_LENGTH: usize = 256;
+/// Height of the drawn paths above the terrain, so they are not hidden by it.
+const TRAJECTORY_OFFSET: f32 = 0.3;
+
+/// Records the paths of a few erosion drops and draws them over the terrain.
+#[derive(Resource, Clone, ExtractResource)]
+pub struct TrajectorySettings {
+    pub enabled: bool,
+    /// Sediment drawn with the warmest color.
+    pub max_sediment: f32,
+}
+
+impl Default for TrajectorySettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_sediment: 0.5,
+        }
+    }
+}
+
+/// One step of a recorded drop.
+#[derive(Clone, Copy, Debug)]
+pub struct TrajectoryPoint {
+    /// Position in cells.
+    pub position: Vec2,
+    pub volume: f32,
+    pub sediment: f32,
+}
+
+/// Paths of the recorded droplets since they last spawned, read back from the GPU.
+#[derive(Resource, Default)]
+pub struct DropletTrajectories(pub Vec<Vec<TrajectoryPoint>>);
+
+/// Marks the readback of the trajectory buffer, which only exists while recording.
+#[derive(Component)]
+pub struct TrajectoryReadback;
+
+/// Buffer of [`TRAJECTORY_LENGTH`] `vec4`s per drop written by the `update` kernel, initially
+/// holding empty trajectories.
+pub fn build_trajectory_buffer() -> ShaderStorageBuffer {
+    let empty: [f32; 4] = [0.0, 0.0, -1.0, 0.0];
+    let data: Vec<u8> = empty
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .cycle()
+        .take(TRAJECTORY_COUNT * TRAJECTORY_LENGTH * 16)
+        .collect();
+
+    let mut buffer = ShaderStorageBuffer::new(&data, RenderAssetUsages::RENDER_WORLD);
+    buffer.buffer_description.usage |= BufferUsages::COPY_SRC;
+    buffer
+}
+
+/// Reads the trajectory buffer back while recording is enabled.
+pub fn toggle_trajectory_readback(
+    mut commands: Commands,
+    settings: Res<TrajectorySettings>,
+    hydrology_image: Res<HydrologyImage>,
+    readbacks: Query<Entity, With<TrajectoryReadback>>,
+    mut trajectories: ResMut<DropletTrajectories>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+
+    match (settings.enabled, readbacks.get_single()) {
+        (true, Err(_)) => {
+            commands
+                .spawn((
+                    TrajectoryReadback,
+                    Readback::buffer(hydrology_image.trajectories.clone()),
+                ))
+                .observe(read_trajectories);
+        }
+        (false, Ok(entity)) => {
+            commands.entity(entity).despawn();
+            trajectories.0.clear();
+        }
+        _ => {}
+    }
+}
+
+/// Observer of the trajectory buffer [`Readback`].
+fn read_trajectories(
+    trigger: Trigger<ReadbackComplete>,
+    mut trajectories: ResMut<DropletTrajectories>,
+) {
+    let points: Vec<TrajectoryPoint> = trigger
+        .chunks_exact(16)
+        .map(|bytes| {
+            let value =
+                |i: usize| f32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+            TrajectoryPoint {
+                position: Vec2::new(value(0), value(4)),
+                volume: value(8),
+                sediment: value(12),
+            }
+        })
+        .collect();
+
+    trajectories.0 = points
+        .chunks_exact(TRAJECTORY_LENGTH)
+        .map(|trajectory| {
+            trajectory
+                .iter()
+                .take_while(|point| point.volume >= 0.0)
+                .copied()
+                .collect()
+        })
+        .collect();
+}
+
+/// Draws the recorded paths, from blue without sediment to red at
+/// [`TrajectorySettings::max_sediment`].
+pub fn draw_trajectories(
+    settings: Res<TrajectorySettings>,
+    trajectories: Res<DropletTrajectories>,
+    heights: Res<TerrainHeights>,
+    mut gizmos: Gizmos,
+) {
+    if !settings.enabled {
+        return;
+    }
+
+    for trajectory in &trajectories.0 {
+        gizmos.linestrip_gradient(trajectory.iter().map(|point| {
+            let world = point.position - TERRAIN_SIZE_F32 / 2.0;
+            let height = heights.sample(point.position) + TRAJECTORY_OFFSET;
+            let load = (point.sediment / settings.max_sediment).clamp(0.0, 1.0);
+            (
+                Vec3::new(world.x, height, world.y),
+                LinearRgba::rgb(load, 0.2, 1.0 - load),
+            )
+        }));
+    }
+}
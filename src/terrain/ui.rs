@@ -1,20 +1,279 @@
-use bevy::ecs::system::ResMut;
+use bevy::{
+    asset::AssetServer,
+    color::LinearRgba,
+    ecs::{
+        change_detection::DetectChangesMut,
+        system::{Query, Res, ResMut},
+    },
+};
 use bevy_egui::{
     egui::{self, Pos2, Ui},
     EguiContexts,
 };
 
-use super::{hydrology_compute::HydrologyConfig, TerrainBuildConfig};
+use super::{
+    brush::{BrushCursor, BrushSettings, BrushTool},
+    chunks::{TerrainChunk, TerrainLod, LOD_COUNT},
+    droplets::{DropletParticleSettings, MAX_DROPLET_COUNT, MIN_DROPLET_COUNT},
+    export::{ExportFormat, MeshExport},
+    graph::TerrainGraphSource,
+    heights::TerrainHeights,
+    history::TerrainHistory,
+    hydrology_compute::HydrologyConfig,
+    live_config::LiveConfigFile,
+    masks::{TerrainMask, TerrainMaskImage},
+    operators::{
+        ApplyHeightOperators, CurveInterpolation, HeightCurve, HeightOperators, MAX_CURVE_POINTS,
+    },
+    presets::{PresetConfig, Presets},
+    project::{ProjectAction, ProjectFile},
+    rendering::{
+        DebugViewSettings, ElevationRamp, MapCamera, MapOverlaySettings, TerrainDebugView,
+        TerrainRenderSettings, TerrainShading,
+    },
+    splat::{SplatExport, SplatLayer, SplatRange, SplatSettings, MIN_SPLAT_BLEND},
+    trajectories::TrajectorySettings,
+    uniforms::HeightmapUpload,
+    TerrainBuildConfig, TerrainGenerator, TerrainRebuild,
+};
+
+/// Preset dropdown and "Save as preset" for one kind of parameters.
+pub fn preset_ui<T: PresetConfig>(presets: &mut Presets<T>, config: &mut T, ui: &mut Ui) {
+    let mut chosen = None;
+    egui::ComboBox::from_label("Preset")
+        .selected_text(presets.library.selected.clone())
+        .show_ui(ui, |ui| {
+            for (name, preset) in presets.list() {
+                let selected = presets.library.selected == name;
+                if ui.selectable_label(selected, &name).clicked() {
+                    chosen = Some((name, preset.clone()));
+                }
+            }
+        });
+    if let Some((name, preset)) = chosen {
+        config.apply_preset(&preset);
+        presets.library.selected = name;
+    }
+    ui.end_row();
+
+    ui.horizontal(|ui| {
+        ui.text_edit_singleline(&mut presets.library.save_name);
+        let name = presets.library.save_name.trim().to_owned();
+        if ui
+            .add_enabled(!name.is_empty(), egui::Button::new("Save as preset"))
+            .clicked()
+        {
+            presets.library.status = match presets.save(&name, config) {
+                Ok(()) => {
+                    presets.library.selected = name.clone();
+                    format!("Saved preset {name}")
+                }
+                Err(error) => error.to_string(),
+            };
+        }
+    });
+    ui.end_row();
+    if !presets.library.status.is_empty() {
+        ui.label(&presets.library.status);
+        ui.end_row();
+    }
+}
+
+pub fn terrain_ui(
+    config: &mut TerrainBuildConfig,
+    rebuild: &mut TerrainRebuild,
+    mask_image: &mut TerrainMaskImage,
+    graph_source: &mut TerrainGraphSource,
+    asset_server: &AssetServer,
+    ui: &mut Ui,
+) {
+    ui.horizontal(|ui| {
+        ui.label("Generator");
+        ui.radio_value(&mut config.generator, TerrainGenerator::Gpu, "GPU");
+        ui.radio_value(&mut config.generator, TerrainGenerator::Cpu, "CPU");
+        ui.radio_value(&mut config.generator, TerrainGenerator::Graph, "Graph");
+    });
+    ui.end_row();
+
+    if config.generator == TerrainGenerator::Graph {
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut graph_source.path);
+            if ui.button("Load").clicked() {
+                graph_source.graph = Some(asset_server.load(graph_source.path.clone()));
+            }
+        });
+        ui.end_row();
+    } else {
+        noise_ui(config, mask_image, asset_server, ui);
+    }
+
+    if ui.button("Rebuild terrain").clicked() {
+        rebuild.generation = rebuild.generation.wrapping_add(1);
+    };
+    ui.end_row();
+}
 
-pub fn terrain_ui(config: &mut TerrainBuildConfig, ui: &mut Ui) {
+fn noise_ui(
+    config: &mut TerrainBuildConfig,
+    mask_image: &mut TerrainMaskImage,
+    asset_server: &AssetServer,
+    ui: &mut Ui,
+) {
     ui.add(egui::Slider::new(&mut config.seed, 0..=120).text("Seed"));
     ui.end_row();
     ui.add(egui::Slider::new(&mut config.base_amplitude, 0.0..=120.0).text("Base amplitude"));
     ui.end_row();
     ui.add(egui::Slider::new(&mut config.base_frequency, 0.0005..=0.05).text("Base frequency"));
     ui.end_row();
+    ui.add(egui::Slider::new(&mut config.warp_levels, 0..=2).text("Warp levels"));
+    ui.end_row();
+    ui.add(egui::Slider::new(&mut config.warp_strength, 0.0..=150.0).text("Warp strength"));
+    ui.end_row();
+    ui.add(egui::Slider::new(&mut config.warp_frequency, 0.0005..=0.05).text("Warp frequency"));
+    ui.end_row();
 
-    if ui.button("Rebuild terrain").clicked() {};
+    egui::ComboBox::from_label("Mask")
+        .selected_text(format!("{:?}", config.mask))
+        .show_ui(ui, |ui| {
+            ui.selectable_value(&mut config.mask, TerrainMask::None, "None");
+            ui.selectable_value(&mut config.mask, TerrainMask::Island, "Island");
+            ui.selectable_value(&mut config.mask, TerrainMask::Square, "Square");
+            ui.selectable_value(&mut config.mask, TerrainMask::Image, "Image");
+        });
+    ui.end_row();
+    match config.mask {
+        TerrainMask::None => {}
+        TerrainMask::Island | TerrainMask::Square => {
+            ui.add(egui::Slider::new(&mut config.mask_radius, 0.0..=1.5).text("Mask radius"));
+            ui.end_row();
+            ui.add(egui::Slider::new(&mut config.mask_falloff, 0.0..=1.0).text("Mask falloff"));
+            ui.end_row();
+        }
+        TerrainMask::Image => {
+            ui.horizontal(|ui| {
+                ui.text_edit_singleline(&mut mask_image.path);
+                if ui.button("Load").clicked() {
+                    mask_image.image = Some(asset_server.load(mask_image.path.clone()));
+                }
+            });
+            ui.end_row();
+        }
+    }
+    ui.checkbox(&mut config.sea_cutoff, "Sea level cutoff");
+    ui.end_row();
+    ui.add_enabled(
+        config.sea_cutoff,
+        egui::Slider::new(&mut config.sea_level, -20.0..=60.0).text("Sea level"),
+    );
+    ui.end_row();
+}
+
+/// Plots the curve over the unit square. Drag points to move them, double click to add one and
+/// right click to remove one.
+fn curve_editor(curve: &mut HeightCurve, ui: &mut Ui) {
+    let (response, painter) = ui.allocate_painter(egui::vec2(220.0, 140.0), egui::Sense::click());
+    let rect = response.rect;
+    let to_screen = |point: [f32; 2]| {
+        egui::pos2(
+            rect.left() + point[0] * rect.width(),
+            rect.bottom() - point[1] * rect.height(),
+        )
+    };
+    let from_screen = |pos: Pos2| {
+        [
+            ((pos.x - rect.left()) / rect.width()).clamp(0.0, 1.0),
+            ((rect.bottom() - pos.y) / rect.height()).clamp(0.0, 1.0),
+        ]
+    };
+
+    painter.rect_filled(rect, 0.0, ui.visuals().extreme_bg_color);
+    let line = (0..=64)
+        .map(|i| {
+            let t = i as f32 / 64.0;
+            to_screen([t, curve.sample(t)])
+        })
+        .collect();
+    painter.add(egui::Shape::line(
+        line,
+        ui.visuals().widgets.active.fg_stroke,
+    ));
+
+    let mut removed = None;
+    for i in 0..curve.points.len() {
+        let center = to_screen(curve.points[i]);
+        let point_response = ui.interact(
+            egui::Rect::from_center_size(center, egui::Vec2::splat(12.0)),
+            response.id.with(i),
+            egui::Sense::click_and_drag(),
+        );
+
+        if point_response.dragged() {
+            if let Some(pos) = point_response.interact_pointer_pos() {
+                // Keep the points sorted by clamping between the neighbours.
+                let min_x = if i > 0 { curve.points[i - 1][0] } else { 0.0 };
+                let max_x = curve.points.get(i + 1).map_or(1.0, |next| next[0]);
+                let [x, y] = from_screen(pos);
+                curve.points[i] = [x.clamp(min_x, max_x), y];
+            }
+        }
+        if point_response.secondary_clicked() {
+            removed = Some(i);
+        }
+
+        painter.circle_filled(center, 4.0, ui.visuals().selection.bg_fill);
+    }
+
+    if let Some(i) = removed.filter(|_| curve.points.len() > 2) {
+        curve.points.remove(i);
+    }
+    if response.double_clicked() && curve.points.len() < MAX_CURVE_POINTS {
+        if let Some(pos) = response.interact_pointer_pos() {
+            let point = from_screen(pos);
+            let index = curve.points.partition_point(|p| p[0] < point[0]);
+            curve.points.insert(index, point);
+        }
+    }
+}
+
+pub fn operators_ui(
+    operators: &mut HeightOperators,
+    apply: &mut ApplyHeightOperators,
+    ui: &mut Ui,
+) {
+    curve_editor(&mut operators.curve, ui);
+    ui.end_row();
+    ui.horizontal(|ui| {
+        ui.radio_value(
+            &mut operators.curve.interpolation,
+            CurveInterpolation::Linear,
+            "Linear",
+        );
+        ui.radio_value(
+            &mut operators.curve.interpolation,
+            CurveInterpolation::Smooth,
+            "Smooth",
+        );
+        if ui.button("Reset curve").clicked() {
+            operators.curve = HeightCurve::default();
+        }
+    });
+    ui.end_row();
+    ui.add(egui::Slider::new(&mut operators.terrace_steps, 0..=32).text("Terrace steps"));
+    ui.end_row();
+    ui.add(
+        egui::Slider::new(&mut operators.terrace_sharpness, 0.0..=1.0).text("Terrace sharpness"),
+    );
+    ui.end_row();
+    ui.add(egui::Slider::new(&mut operators.plateau_min, 0.0..=1.0).text("Plateau min"));
+    ui.end_row();
+    ui.add(egui::Slider::new(&mut operators.plateau_max, 0.0..=1.0).text("Plateau max"));
+    ui.end_row();
+    ui.checkbox(&mut operators.apply_on_rebuild, "Apply on rebuild");
+    ui.end_row();
+
+    if ui.button("Apply to heightmap").clicked() {
+        apply.generation = apply.generation.wrapping_add(1);
+    };
     ui.end_row();
 }
 
@@ -29,15 +288,29 @@ pub fn hydrology_ui(config: &mut HydrologyConfig, ui: &mut Ui) {
     ui.end_row();
     ui.add(egui::Slider::new(&mut config.friction, 0.5..=0.005).text("Friction"));
     ui.end_row();
+    ui.add(egui::Slider::new(&mut config.min_volume, 0.001..=0.1).text("Minimum volume"));
+    ui.end_row();
     ui.add(
-        egui::Slider::new(&mut config.drops_per_frame_per_chunk, 0..=2048).text("Drops per frame"),
+        egui::Slider::new(
+            &mut config.droplet_count,
+            MIN_DROPLET_COUNT..=MAX_DROPLET_COUNT,
+        )
+        .logarithmic(true)
+        .text("Droplets"),
     );
     ui.end_row();
-    ui.add(egui::Slider::new(&mut config.min_volume, 0.001..=0.1).text("Minimum volume"));
+    ui.add(
+        egui::Slider::new(&mut config.droplet_steps_per_frame, 1..=512)
+            .logarithmic(true)
+            .text("Droplet steps per frame"),
+    );
     ui.end_row();
-    ui.add(egui::Slider::new(&mut config.max_drops, 0..=400_000).text("Maximum drops"));
+    ui.add(egui::Slider::new(&mut config.max_droplet_age, 16..=4000).text("Droplet lifetime"));
     ui.end_row();
-    ui.label("Drops count: ".to_owned() + &config.drop_count.to_string());
+    ui.label(format!(
+        "Steps per frame: {}",
+        config.droplet_steps_per_frame * config.clamped_droplet_count()
+    ));
     ui.end_row();
 
     if ui.button("Reset to defaults").clicked() {
@@ -48,15 +321,508 @@ pub fn hydrology_ui(config: &mut HydrologyConfig, ui: &mut Ui) {
         config.deposition_rate = default.deposition_rate;
         config.friction = default.friction;
         config.min_volume = default.min_volume;
-        config.drops_per_frame_per_chunk = default.drops_per_frame_per_chunk;
-        config.drop_count = default.drop_count;
-        config.max_drops = default.max_drops;
+        config.droplet_count = default.droplet_count;
+        config.droplet_steps_per_frame = default.droplet_steps_per_frame;
+        config.max_droplet_age = default.max_droplet_age;
     };
 }
 
+pub fn trajectories_ui(settings: &mut TrajectorySettings, ui: &mut Ui) {
+    ui.checkbox(&mut settings.enabled, "Record drop trajectories");
+    ui.end_row();
+    ui.add_enabled_ui(settings.enabled, |ui| {
+        ui.add(
+            egui::Slider::new(&mut settings.max_sediment, 0.01..=5.0)
+                .logarithmic(true)
+                .text("Max sediment color"),
+        );
+    });
+    ui.end_row();
+}
+
+pub fn droplet_particles_ui(settings: &mut DropletParticleSettings, ui: &mut Ui) {
+    ui.checkbox(&mut settings.visible, "Show droplets");
+    ui.end_row();
+    ui.add_enabled_ui(settings.visible, |ui| {
+        ui.add(egui::Slider::new(&mut settings.size, 0.1..=4.0).text("Droplet size"));
+    });
+    ui.end_row();
+    color_ui("Droplet color", &mut settings.color, ui);
+}
+
+pub fn brush_ui(settings: &mut BrushSettings, cursor: &BrushCursor, ui: &mut Ui) {
+    ui.horizontal_wrapped(|ui| {
+        ui.radio_value(&mut settings.tool, BrushTool::None, "None");
+        ui.radio_value(&mut settings.tool, BrushTool::Raise, "Raise");
+        ui.radio_value(&mut settings.tool, BrushTool::Lower, "Lower");
+        ui.radio_value(&mut settings.tool, BrushTool::Smooth, "Smooth");
+        ui.radio_value(&mut settings.tool, BrushTool::Flatten, "Flatten");
+        ui.radio_value(&mut settings.tool, BrushTool::Noise, "Noise");
+        ui.radio_value(&mut settings.tool, BrushTool::Rain, "Rain");
+    });
+    ui.end_row();
+    ui.add(egui::Slider::new(&mut settings.radius, 1.0..=64.0).text("Radius"));
+    ui.end_row();
+    if settings.tool != BrushTool::Rain {
+        ui.add(egui::Slider::new(&mut settings.strength, 0.5..=50.0).text("Strength"));
+        ui.end_row();
+    }
+    if settings.tool == BrushTool::Noise {
+        ui.add(
+            egui::Slider::new(&mut settings.noise_frequency, 0.005..=0.2).text("Noise frequency"),
+        );
+        ui.end_row();
+    }
+
+    match cursor.0 {
+        Some(hit) => ui.label(format!(
+            "Cursor: {:.0}, {:.0} at height {:.1}",
+            hit.x, hit.z, hit.y
+        )),
+        None => ui.label("Cursor: not over the terrain"),
+    };
+    ui.end_row();
+    match settings.tool {
+        BrushTool::None => {}
+        BrushTool::Rain => {
+            ui.label("Left drag to rain, Alt + left drag to orbit");
+            ui.end_row();
+            ui.label("Erosion is paused while not raining");
+            ui.end_row();
+        }
+        _ => {
+            ui.label("Left drag to sculpt, Alt + left drag to orbit");
+            ui.end_row();
+        }
+    }
+}
+
+pub fn sculpt_ui_system(
+    mut brush_settings: ResMut<BrushSettings>,
+    brush_cursor: Res<BrushCursor>,
+    mut contexts: EguiContexts,
+) {
+    egui::Window::new("Sculpting")
+        .current_pos(Pos2 { x: 1600., y: 10. })
+        .show(contexts.ctx_mut(), |ui| {
+            egui::Grid::new("sculpting_grid")
+                .num_columns(2)
+                .spacing([40.0, 4.0])
+                .striped(true)
+                .show(ui, |ui| {
+                    brush_ui(brush_settings.as_mut(), &brush_cursor, ui);
+                });
+        });
+}
+
+/// Undo or redo picked in [`history_ui`], with the number of steps.
+pub enum HistoryTravel {
+    Undo(usize),
+    Redo(usize),
+}
+
+/// Lists the applied actions followed by the undone ones. Clicking an entry travels to the state
+/// right after it.
+pub fn history_ui(history: &mut TerrainHistory, ui: &mut Ui) -> Option<HistoryTravel> {
+    let mut travel = None;
+    ui.horizontal(|ui| {
+        if ui
+            .add_enabled(!history.undo.is_empty(), egui::Button::new("Undo"))
+            .clicked()
+        {
+            travel = Some(HistoryTravel::Undo(1));
+        }
+        if ui
+            .add_enabled(!history.redo.is_empty(), egui::Button::new("Redo"))
+            .clicked()
+        {
+            travel = Some(HistoryTravel::Redo(1));
+        }
+    });
+
+    let mut limit_mb = history.memory_limit / (1024 * 1024);
+    ui.add(egui::Slider::new(&mut limit_mb, 1..=1024).text("Memory limit (MB)"));
+    history.memory_limit = limit_mb * 1024 * 1024;
+    ui.label(format!(
+        "Memory used: {:.1} MB",
+        history.memory_usage() as f32 / (1024.0 * 1024.0)
+    ));
+    ui.add(
+        egui::Slider::new(&mut history.erosion_batch_seconds, 1.0..=120.0)
+            .text("Erosion batch (s)"),
+    );
+    ui.separator();
+
+    egui::ScrollArea::vertical()
+        .max_height(240.0)
+        .show(ui, |ui| {
+            if ui
+                .selectable_label(false, "Start")
+                .on_hover_text("Undo everything")
+                .clicked()
+            {
+                travel = Some(HistoryTravel::Undo(history.undo.len()));
+            }
+            let applied = history.undo.len();
+            for (i, snapshot) in history.undo.iter().enumerate() {
+                if ui
+                    .selectable_label(i + 1 == applied, &snapshot.label)
+                    .clicked()
+                {
+                    travel = Some(HistoryTravel::Undo(applied - i - 1));
+                }
+            }
+            for (i, snapshot) in history.redo.iter().rev().enumerate() {
+                let label = egui::RichText::new(&snapshot.label).weak();
+                if ui.selectable_label(false, label).clicked() {
+                    travel = Some(HistoryTravel::Redo(i + 1));
+                }
+            }
+        });
+
+    travel
+}
+
+pub fn history_ui_system(
+    mut history: ResMut<TerrainHistory>,
+    mut heights: ResMut<TerrainHeights>,
+    mut upload: ResMut<HeightmapUpload>,
+    mut contexts: EguiContexts,
+) {
+    egui::Window::new("History")
+        .current_pos(Pos2 { x: 1600., y: 260. })
+        .show(contexts.ctx_mut(), |ui| {
+            // The heights are only borrowed mutably when they change, as that marks them changed.
+            match history_ui(history.as_mut(), ui) {
+                Some(HistoryTravel::Undo(steps)) => {
+                    history.undo(steps, heights.as_mut(), upload.as_mut())
+                }
+                Some(HistoryTravel::Redo(steps)) => {
+                    history.redo(steps, heights.as_mut(), upload.as_mut())
+                }
+                None => {}
+            }
+        });
+}
+
+pub fn project_ui(project_file: &mut ProjectFile, ui: &mut Ui) {
+    ui.text_edit_singleline(&mut project_file.path);
+    ui.horizontal(|ui| {
+        if ui.button("Save").clicked() {
+            project_file.pending = Some(ProjectAction::Save);
+        }
+        if ui.button("Load").clicked() {
+            project_file.pending = Some(ProjectAction::Load);
+        }
+    });
+    if !project_file.status.is_empty() {
+        ui.label(&project_file.status);
+    }
+}
+
+/// Watching a file loads it, and keeps it loaded so hot reloading applies its edits.
+pub fn live_config_ui(
+    live_config_file: &mut LiveConfigFile,
+    asset_server: &AssetServer,
+    ui: &mut Ui,
+) {
+    ui.horizontal(|ui| {
+        ui.label("Live config");
+        ui.add_enabled(
+            live_config_file.handle.is_none(),
+            egui::TextEdit::singleline(&mut live_config_file.path),
+        );
+    });
+
+    let mut watched = live_config_file.handle.is_some();
+    if ui.checkbox(&mut watched, "Watch for changes").changed() {
+        live_config_file.handle = watched.then(|| asset_server.load(live_config_file.path.clone()));
+    }
+}
+
+pub fn export_ui(export: &mut MeshExport, ui: &mut Ui) {
+    ui.horizontal(|ui| {
+        ui.label("Export mesh");
+        ui.text_edit_singleline(&mut export.path);
+        egui::ComboBox::from_id_salt("export_format")
+            .selected_text(format!(".{}", export.format.extension()))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut export.format, ExportFormat::Gltf, "glTF (.glb)");
+                ui.selectable_value(&mut export.format, ExportFormat::Obj, "OBJ");
+                ui.selectable_value(&mut export.format, ExportFormat::Stl, "STL");
+            });
+    });
+    ui.add(egui::Slider::new(&mut export.step, 1..=16).text("Vertex spacing"));
+    if ui.button("Export").clicked() {
+        export.pending = true;
+    }
+    if !export.status.is_empty() {
+        ui.label(&export.status);
+    }
+}
+
+pub fn splat_export_ui(export: &mut SplatExport, ui: &mut Ui) {
+    ui.horizontal(|ui| {
+        ui.label("Export splat maps");
+        ui.text_edit_singleline(&mut export.path);
+        ui.label("_0.png, _1.png");
+    });
+    if ui.button("Export splat maps").clicked() {
+        export.pending = true;
+    }
+    if !export.status.is_empty() {
+        ui.label(&export.status);
+    }
+}
+
+pub fn project_ui_system(
+    mut project_file: ResMut<ProjectFile>,
+    mut live_config_file: ResMut<LiveConfigFile>,
+    mut mesh_export: ResMut<MeshExport>,
+    mut splat_export: ResMut<SplatExport>,
+    asset_server: Res<AssetServer>,
+    mut contexts: EguiContexts,
+) {
+    egui::Window::new("Project")
+        .current_pos(Pos2 { x: 1600., y: 620. })
+        .show(contexts.ctx_mut(), |ui| {
+            project_ui(project_file.as_mut(), ui);
+            ui.separator();
+            live_config_ui(live_config_file.as_mut(), &asset_server, ui);
+            ui.separator();
+            export_ui(mesh_export.as_mut(), ui);
+            ui.separator();
+            splat_export_ui(splat_export.as_mut(), ui);
+        });
+}
+
+pub fn lod_ui(lod: &mut TerrainLod, chunks: &Query<&TerrainChunk>, ui: &mut Ui) {
+    ui.add(egui::Slider::new(&mut lod.distance, 16.0..=512.0).text("LOD distance"));
+    ui.end_row();
+
+    let mut counts = [0; LOD_COUNT];
+    for chunk in chunks {
+        counts[chunk.lod] += 1;
+    }
+    ui.label(format!("Chunks per LOD: {counts:?}"));
+    ui.end_row();
+}
+
+/// Label and color picker on one grid row.
+pub fn color_ui(label: &str, color: &mut LinearRgba, ui: &mut Ui) {
+    ui.label(label);
+    let mut rgba =
+        egui::Rgba::from_rgba_unmultiplied(color.red, color.green, color.blue, color.alpha);
+    if egui::color_picker::color_edit_button_rgba(
+        ui,
+        &mut rgba,
+        egui::color_picker::Alpha::OnlyBlend,
+    )
+    .changed()
+    {
+        let [red, green, blue, alpha] = rgba.to_rgba_unmultiplied();
+        *color = LinearRgba::new(red, green, blue, alpha);
+    }
+    ui.end_row();
+}
+
+pub fn shading_ui(settings: &mut TerrainRenderSettings, ui: &mut Ui) {
+    ui.label("Shading");
+    ui.horizontal(|ui| {
+        ui.selectable_value(&mut settings.shading, TerrainShading::Flat, "Flat");
+        ui.selectable_value(&mut settings.shading, TerrainShading::Smooth, "Smooth");
+    });
+    ui.end_row();
+    ui.add_enabled_ui(settings.shading == TerrainShading::Smooth, |ui| {
+        ui.checkbox(&mut settings.normal_mapping, "Per pixel normals");
+    });
+    ui.end_row();
+}
+
+pub fn rivers_ui(settings: &mut TerrainRenderSettings, ui: &mut Ui) {
+    ui.checkbox(&mut settings.show_rivers, "Show rivers");
+    ui.end_row();
+    ui.add_enabled_ui(settings.show_rivers, |ui| {
+        ui.add(
+            egui::Slider::new(&mut settings.river_threshold, 0.01..=10.0)
+                .logarithmic(true)
+                .text("Discharge threshold"),
+        );
+    });
+    ui.end_row();
+    ui.add_enabled_ui(settings.show_rivers, |ui| {
+        ui.add(egui::Slider::new(&mut settings.river_flow_speed, 0.0..=10.0).text("Flow speed"));
+    });
+    ui.end_row();
+    color_ui("River color", &mut settings.river_color, ui);
+}
+
+/// Minimum and maximum of a [`SplatRange`] on one grid row.
+fn splat_range_ui(label: &str, range: &mut SplatRange, speed: f64, ui: &mut Ui) {
+    ui.label(label);
+    ui.horizontal(|ui| {
+        ui.add(egui::DragValue::new(&mut range.min).speed(speed));
+        ui.label("to");
+        ui.add(egui::DragValue::new(&mut range.max).speed(speed));
+    });
+    ui.end_row();
+}
+
+pub fn splat_ui(settings: &mut SplatSettings, ui: &mut Ui) {
+    ui.checkbox(&mut settings.enabled, "Splat layers");
+    ui.end_row();
+    ui.add(egui::Slider::new(&mut settings.blend, MIN_SPLAT_BLEND..=4.0).text("Layer blend"));
+    ui.end_row();
+    ui.add(
+        egui::Slider::new(&mut settings.texture_scale, 0.01..=2.0)
+            .logarithmic(true)
+            .text("Texture scale"),
+    );
+    ui.end_row();
+
+    for (layer, rule) in SplatLayer::ALL.iter().zip(&mut settings.rules) {
+        ui.strong(format!("{layer:?}"));
+        ui.end_row();
+        color_ui("Color", &mut rule.color, ui);
+        ui.add(egui::Slider::new(&mut rule.strength, 0.0..=2.0).text("Strength"));
+        ui.end_row();
+        splat_range_ui("Altitude", &mut rule.altitude, 0.5, ui);
+        splat_range_ui("Slope", &mut rule.slope, 0.01, ui);
+        splat_range_ui("Moisture", &mut rule.moisture, 0.01, ui);
+        splat_range_ui("Erosion", &mut rule.erosion, 0.01, ui);
+    }
+}
+
+pub fn map_ui(settings: &mut MapOverlaySettings, map_camera: &mut MapCamera, ui: &mut Ui) {
+    ui.checkbox(&mut map_camera.top_down, "Top-down orthographic camera");
+    ui.end_row();
+
+    ui.checkbox(&mut settings.contours, "Contour lines");
+    ui.end_row();
+    ui.add_enabled_ui(settings.contours, |ui| {
+        ui.add(egui::Slider::new(&mut settings.minor_interval, 0.25..=20.0).text("Minor interval"));
+    });
+    ui.end_row();
+    ui.add_enabled_ui(settings.contours, |ui| {
+        ui.add(egui::Slider::new(&mut settings.major_interval, 1.0..=100.0).text("Major interval"));
+    });
+    ui.end_row();
+    color_ui("Contour color", &mut settings.contour_color, ui);
+
+    ui.checkbox(&mut settings.hypsometric_tint, "Hypsometric tint");
+    ui.end_row();
+    ui.label("Elevation ramp");
+    egui::ComboBox::from_id_salt("elevation_ramp")
+        .selected_text(settings.ramp.label())
+        .show_ui(ui, |ui| {
+            for ramp in ElevationRamp::ALL {
+                ui.selectable_value(&mut settings.ramp, ramp, ramp.label());
+            }
+        });
+    ui.end_row();
+    ui.add(egui::Slider::new(&mut settings.ramp_min, -20.0..=100.0).text("Ramp min height"));
+    ui.end_row();
+    ui.add(egui::Slider::new(&mut settings.ramp_max, -20.0..=100.0).text("Ramp max height"));
+    ui.end_row();
+    ui.add(egui::Slider::new(&mut settings.tint_strength, 0.0..=1.0).text("Tint strength"));
+    ui.end_row();
+}
+
+pub fn debug_view_ui(settings: &mut DebugViewSettings, ui: &mut Ui) {
+    ui.label("Debug view");
+    egui::ComboBox::from_id_salt("debug_view")
+        .selected_text(settings.view.label())
+        .show_ui(ui, |ui| {
+            for view in TerrainDebugView::ALL {
+                ui.selectable_value(&mut settings.view, view, view.label());
+            }
+        });
+    ui.end_row();
+
+    match settings.view {
+        TerrainDebugView::Height => {
+            ui.add(egui::Slider::new(&mut settings.height_min, -20.0..=100.0).text("Min height"));
+            ui.end_row();
+            ui.add(egui::Slider::new(&mut settings.height_max, -20.0..=100.0).text("Max height"));
+            ui.end_row();
+        }
+        TerrainDebugView::Curvature
+        | TerrainDebugView::Erosion
+        | TerrainDebugView::DropDensity
+        | TerrainDebugView::Discharge => {
+            ui.add(
+                egui::Slider::new(&mut settings.scale, 0.01..=100.0)
+                    .logarithmic(true)
+                    .text("Scale"),
+            );
+            ui.end_row();
+        }
+        TerrainDebugView::Contours => {
+            ui.add(egui::Slider::new(&mut settings.contour_interval, 0.25..=20.0).text("Interval"));
+            ui.end_row();
+        }
+        TerrainDebugView::None | TerrainDebugView::Normals | TerrainDebugView::Slope => {}
+    }
+}
+
+pub fn rendering_ui_system(
+    mut lod: ResMut<TerrainLod>,
+    mut render_settings: ResMut<TerrainRenderSettings>,
+    mut map_camera: ResMut<MapCamera>,
+    chunks: Query<&TerrainChunk>,
+    mut contexts: EguiContexts,
+) {
+    egui::Window::new("Rendering")
+        .current_pos(Pos2 { x: 1300., y: 10. })
+        .show(contexts.ctx_mut(), |ui| {
+            // Edited on a copy, so the material is only updated when something changed.
+            let mut settings = render_settings.clone();
+            egui::Grid::new("rendering_grid")
+                .num_columns(2)
+                .spacing([40.0, 4.0])
+                .striped(true)
+                .show(ui, |ui| {
+                    lod_ui(lod.as_mut(), &chunks, ui);
+                    shading_ui(&mut settings, ui);
+                    rivers_ui(&mut settings, ui);
+                    debug_view_ui(&mut settings.debug, ui);
+                });
+            egui::CollapsingHeader::new("Splat layers").show(ui, |ui| {
+                egui::Grid::new("splat_grid")
+                    .num_columns(2)
+                    .spacing([40.0, 4.0])
+                    .striped(true)
+                    .show(ui, |ui| {
+                        splat_ui(&mut settings.splat, ui);
+                    });
+            });
+            egui::CollapsingHeader::new("Map").show(ui, |ui| {
+                egui::Grid::new("map_grid")
+                    .num_columns(2)
+                    .spacing([40.0, 4.0])
+                    .striped(true)
+                    .show(ui, |ui| {
+                        map_ui(&mut settings.map, map_camera.as_mut(), ui);
+                    });
+            });
+            render_settings.set_if_neq(settings);
+        });
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn ui_system(
     mut terrain_uniform_config: ResMut<TerrainBuildConfig>,
+    mut terrain_rebuild: ResMut<TerrainRebuild>,
+    mut mask_image: ResMut<TerrainMaskImage>,
+    mut graph_source: ResMut<TerrainGraphSource>,
+    asset_server: Res<AssetServer>,
+    mut height_operators: ResMut<HeightOperators>,
+    mut apply_height_operators: ResMut<ApplyHeightOperators>,
     mut hydrology_config: ResMut<HydrologyConfig>,
+    mut terrain_presets: Presets<TerrainBuildConfig>,
+    mut hydrology_presets: Presets<HydrologyConfig>,
+    mut trajectory_settings: ResMut<TrajectorySettings>,
+    mut droplet_settings: ResMut<DropletParticleSettings>,
     mut contexts: EguiContexts,
 ) {
     egui::Window::new("Terrain Generation")
@@ -67,8 +833,33 @@ pub fn ui_system(
                 .spacing([40.0, 4.0])
                 .striped(true)
                 .show(ui, |ui| {
-                    terrain_ui(terrain_uniform_config.as_mut(), ui);
+                    preset_ui(&mut terrain_presets, terrain_uniform_config.as_mut(), ui);
+                    // Edited on a copy, so the rebuild is only marked as changed when requested.
+                    let mut rebuild = *terrain_rebuild;
+                    terrain_ui(
+                        terrain_uniform_config.as_mut(),
+                        &mut rebuild,
+                        mask_image.as_mut(),
+                        graph_source.as_mut(),
+                        &asset_server,
+                        ui,
+                    );
+                    terrain_rebuild.set_if_neq(rebuild);
                 });
+
+            egui::CollapsingHeader::new("Height operators").show(ui, |ui| {
+                egui::Grid::new("operators_grid")
+                    .num_columns(2)
+                    .spacing([40.0, 4.0])
+                    .striped(true)
+                    .show(ui, |ui| {
+                        // Edited on a copy, so the operators are only marked as applied when
+                        // requested.
+                        let mut apply = *apply_height_operators;
+                        operators_ui(height_operators.as_mut(), &mut apply, ui);
+                        apply_height_operators.set_if_neq(apply);
+                    });
+            });
         });
 
     egui::Window::new("Hydrology")
@@ -79,7 +870,32 @@ pub fn ui_system(
                 .spacing([40.0, 4.0])
                 .striped(true)
                 .show(ui, |ui| {
+                    preset_ui(&mut hydrology_presets, hydrology_config.as_mut(), ui);
                     hydrology_ui(hydrology_config.as_mut(), ui);
                 });
+
+            egui::CollapsingHeader::new("Drop trajectories").show(ui, |ui| {
+                egui::Grid::new("trajectories_grid")
+                    .num_columns(2)
+                    .spacing([40.0, 4.0])
+                    .striped(true)
+                    .show(ui, |ui| {
+                        trajectories_ui(trajectory_settings.as_mut(), ui);
+                    });
+            });
+
+            egui::CollapsingHeader::new("Droplet particles").show(ui, |ui| {
+                egui::Grid::new("droplet_particles_grid")
+                    .num_columns(2)
+                    .spacing([40.0, 4.0])
+                    .striped(true)
+                    .show(ui, |ui| {
+                        // Edited on a copy, so the settings are only marked as changed, and
+                        // extracted, when a value changed.
+                        let mut edited = droplet_settings.clone();
+                        droplet_particles_ui(&mut edited, ui);
+                        droplet_settings.set_if_neq(edited);
+                    });
+            });
         });
 }
@@ -1,27 +1,69 @@
+use std::sync::Arc;
+
 use bevy::{
     prelude::*,
     render::{
         extract_resource::ExtractResource,
-        render_resource::{AsBindGroup, ShaderType, UniformBuffer},
+        render_resource::{AsBindGroup, UniformBuffer},
+        storage::ShaderStorageBuffer,
     },
 };
 
-#[derive(Clone, Resource, ExtractResource, Reflect, ShaderType)]
-#[reflect(Resource, Default)]
-pub struct TerrainUniform {
-    pub(crate) noise_seed: i32,
-    pub(crate) noise_amplitude: f32,
-    pub(crate) noise_base_frequency: f32,
-    pub time_seconds: f32,
-    pub dt: f32,
-    pub density: f32,
-    pub evap_rate: f32,
-    pub deposition_rate: f32,
-    pub min_volume: f32,
-    pub friction: f32,
-    pub drops_per_frame_per_chunck: u32,
-    pub drop_count: u32,
-    pub max_drops: u32,
+pub use self::{
+    render_uniforms::{SplatRuleUniform, TerrainRenderUniform},
+    terrain_uniform::TerrainUniform,
+};
+
+// `ShaderType` generates a field check function next to the struct, which the lint reports as
+// never used. Attributes on the struct do not reach it.
+#[allow(dead_code)]
+mod terrain_uniform {
+    use bevy::{
+        prelude::*,
+        render::{extract_resource::ExtractResource, render_resource::ShaderType},
+    };
+
+    #[derive(Clone, Resource, ExtractResource, Reflect, ShaderType)]
+    #[reflect(Resource, Default)]
+    pub struct TerrainUniform {
+        pub(crate) noise_seed: i32,
+        pub(crate) noise_amplitude: f32,
+        pub(crate) noise_base_frequency: f32,
+        pub(crate) noise_warp_levels: u32,
+        pub(crate) noise_warp_strength: f32,
+        pub(crate) noise_warp_frequency: f32,
+        pub(crate) sea_cutoff: u32,
+        pub(crate) sea_level: f32,
+        pub time_seconds: f32,
+        pub dt: f32,
+        pub density: f32,
+        pub evap_rate: f32,
+        pub deposition_rate: f32,
+        pub min_volume: f32,
+        pub friction: f32,
+        /// [`crate::terrain::operators::HeightCurve`] points, two `(input, output)` pairs per element.
+        pub curve_points: [Vec4; 8],
+        pub curve_point_count: u32,
+        pub curve_smooth: u32,
+        pub terrace_steps: u32,
+        pub terrace_sharpness: f32,
+        pub plateau_min: f32,
+        pub plateau_max: f32,
+        /// [`crate::terrain::brush::BrushStroke`] of the current frame.
+        pub brush_center: Vec2,
+        pub brush_radius: f32,
+        pub brush_strength: f32,
+        pub brush_tool: u32,
+        pub brush_active: u32,
+        pub brush_target_height: f32,
+        pub brush_noise_frequency: f32,
+        pub brush_strokes: u32,
+        /// Whether the `update` kernel records [`crate::terrain::trajectories::DropletTrajectories`].
+        pub record_trajectories: u32,
+        /// [`crate::terrain::hydrology_compute::HydrologyConfig::droplet_steps_per_frame`].
+        pub droplet_steps_per_frame: u32,
+        pub max_droplet_age: u32,
+    }
 }
 
 impl Default for TerrainUniform {
@@ -30,6 +72,11 @@ impl Default for TerrainUniform {
             noise_seed: 96,
             noise_amplitude: 15.0,
             noise_base_frequency: 1.0 / 80.0,
+            noise_warp_levels: 0,
+            noise_warp_strength: 40.0,
+            noise_warp_frequency: 1.0 / 200.0,
+            sea_cutoff: 0,
+            sea_level: 5.0,
             time_seconds: 0.0,
             dt: 1.2,
             density: 1.0,
@@ -37,9 +84,24 @@ impl Default for TerrainUniform {
             deposition_rate: 0.1,
             friction: 0.05,
             min_volume: 0.05,
-            drops_per_frame_per_chunck: 1000,
-            drop_count: 0,
-            max_drops: 200_000,
+            curve_points: [Vec4::ZERO; 8],
+            curve_point_count: 0,
+            curve_smooth: 0,
+            terrace_steps: 0,
+            terrace_sharpness: 0.0,
+            plateau_min: 0.0,
+            plateau_max: 1.0,
+            brush_center: Vec2::ZERO,
+            brush_radius: 0.0,
+            brush_strength: 0.0,
+            brush_tool: 0,
+            brush_active: 0,
+            brush_target_height: 0.0,
+            brush_noise_frequency: 0.0,
+            brush_strokes: 0,
+            record_trajectories: 0,
+            droplet_steps_per_frame: 64,
+            max_droplet_age: 1500,
         }
     }
 }
@@ -60,4 +122,105 @@ pub(crate) struct HydrologyImage {
 
     #[storage_texture(2, image_format = Rgba32Float, access = ReadWrite)]
     pub(crate) normalmap_bottomright: Handle<Image>,
+
+    #[storage_texture(3, image_format = R32Float, access = ReadWrite)]
+    pub(crate) mask: Handle<Image>,
+
+    /// Water flowing through each cell, see `flow_maps` in `erosion.wgsl`.
+    #[storage_texture(4, image_format = R32Float, access = ReadWrite)]
+    pub(crate) discharge: Handle<Image>,
+
+    /// Volume weighted velocity of the water flowing through each cell in `xy`, and the number of
+    /// drop steps taken in it per frame in `z`.
+    #[storage_texture(5, image_format = Rgba32Float, access = ReadWrite)]
+    pub(crate) momentum: Handle<Image>,
+
+    /// Height removed by the drops since the terrain was built or loaded, negative where they
+    /// deposited.
+    #[storage_texture(6, image_format = R32Float, access = ReadWrite)]
+    pub(crate) erosion: Handle<Image>,
+
+    /// Paths of a few drops, see [`super::trajectories::build_trajectory_buffer`].
+    #[storage(7, visibility(compute))]
+    pub(crate) trajectories: Handle<ShaderStorageBuffer>,
+
+    /// State of the drops carried over between frames, see
+    /// [`super::droplets::build_droplet_buffer`].
+    #[storage(8, visibility(compute))]
+    pub(crate) droplets: Handle<ShaderStorageBuffer>,
+}
+
+/// Heights computed on the CPU, written into [`HydrologyImage::heightmap`] by the hydrology node.
+#[derive(Resource, Clone, Default, ExtractResource)]
+pub(crate) struct HeightmapUpload {
+    pub(crate) generation: u32,
+    pub(crate) heights: Option<Arc<Vec<f32>>>,
+    /// Whether the heights are a new terrain, whose discharge, momentum and erosion maps are
+    /// cleared with the upload.
+    pub(crate) new_terrain: bool,
+}
+
+impl HeightmapUpload {
+    /// Uploads edited heights of the current terrain, keeping its flow maps.
+    pub(crate) fn push(&mut self, heights: Arc<Vec<f32>>) {
+        self.generation = self.generation.wrapping_add(1);
+        self.heights = Some(heights);
+        self.new_terrain = false;
+    }
+
+    /// Uploads the heights of a rebuilt or loaded terrain.
+    pub(crate) fn push_terrain(&mut self, heights: Arc<Vec<f32>>) {
+        self.push(heights);
+        self.new_terrain = true;
+    }
+}
+
+// Same as `terrain_uniform`.
+#[allow(dead_code)]
+mod render_uniforms {
+    use bevy::{prelude::*, render::render_resource::ShaderType};
+
+    use crate::terrain::splat::SPLAT_LAYER_COUNT;
+
+    /// [`crate::terrain::rendering::TerrainRenderSettings`] as seen by `terrain_material.wgsl`.
+    #[derive(Clone, Copy, Debug, Reflect, ShaderType)]
+    pub struct TerrainRenderUniform {
+        pub river_color: Vec4,
+        pub river_threshold: f32,
+        pub river_flow_speed: f32,
+        pub show_rivers: u32,
+        pub smooth_shading: u32,
+        pub normal_mapping: u32,
+        pub splat_enabled: u32,
+        pub splat_blend: f32,
+        pub splat_texture_scale: f32,
+        pub contour_color: Vec4,
+        pub contours: u32,
+        pub contour_minor_interval: f32,
+        pub contour_major_interval: f32,
+        pub hypsometric_tint: u32,
+        /// [`crate::terrain::rendering::ElevationRamp`] as its index.
+        pub elevation_ramp: u32,
+        pub ramp_min: f32,
+        pub ramp_max: f32,
+        pub tint_strength: f32,
+        /// [`crate::terrain::rendering::TerrainDebugView`] as its index, 0 to shade the terrain normally.
+        pub debug_view: u32,
+        pub debug_height_min: f32,
+        pub debug_height_max: f32,
+        pub debug_scale: f32,
+        pub debug_contour_interval: f32,
+        pub splat_rules: [SplatRuleUniform; SPLAT_LAYER_COUNT],
+    }
+
+    /// [`crate::terrain::splat::SplatRule`] as seen by `terrain_material.wgsl`.
+    #[derive(Clone, Copy, Debug, Default, Reflect, ShaderType)]
+    pub struct SplatRuleUniform {
+        pub color: Vec4,
+        /// Altitude range in `xy`, slope range in `zw`.
+        pub altitude_slope: Vec4,
+        /// Moisture range in `xy`, erosion range in `zw`.
+        pub moisture_erosion: Vec4,
+        pub strength: f32,
+    }
 }
@@ -1,57 +1,288 @@
-// use bevy::{
-//     prelude::*,
-//     reflect::TypeUuid,
-//     render::render_resource::{AsBindGroup, ShaderRef},
-// };
-
-// pub struct WaterPlugin;
-
-// impl Plugin for WaterPlugin {
-//     fn build(&self, app: &mut App) {
-//         app.add_plugin(MaterialPlugin::<WaterMaerial>::default())
-//             .add_startup_system(setup_water);
-//     }
-// }
-
-// fn setup_water(
-//     mut commands: Commands,
-//     mut meshes: ResMut<Assets<Mesh>>,
-//     mut materials: ResMut<Assets<WaterMaerial>>,
-//     asset_server: Res<AssetServer>,
-// ) {
-//     // cube
-//     commands.spawn(MaterialMeshBundle {
-//         mesh: meshes.add(Mesh::from(shape::Cube { size: 1.0 })),
-//         transform: Transform::from_xyz(0.0, 0.5, 1.0),
-//         material: materials.add(WaterMaerial {
-//             color: Color::BLUE,
-//             color_texture: Some(asset_server.load("textures/bevy.png")),
-//             alpha_mode: AlphaMode::Blend,
-//         }),
-//         ..default()
-//     });
-// }
-
-// /// The Material trait is very configurable, but comes with sensible defaults for all methods.
-// /// You only need to implement functions for features that need non-default behavior. See the Material api docs for details!
-// impl Material for WaterMaerial {
-//     fn fragment_shader() -> ShaderRef {
-//         "shaders/water_material.wgsl".into()
-//     }
-
-//     fn alpha_mode(&self) -> AlphaMode {
-//         self.alpha_mode
-//     }
-// }
-
-// // This is the struct that will be passed to your shader
-// #[derive(AsBindGroup, TypeUuid, Debug, Clone)]
-// #[uuid = "f690fdae-d598-45ab-8225-97e2a3f056e0"]
-// pub struct WaterMaerial {
-//     #[uniform(0)]
-//     color: Color,
-//     #[texture(1)]
-//     #[sampler(2)]
-//     color_texture: Option<Handle<Image>>,
-//     alpha_mode: AlphaMode,
-// }
+use bevy::{
+    pbr::NotShadowCaster,
+    prelude::*,
+    render::{
+        render_resource::{AsBindGroup, ShaderRef},
+        view::NoFrustumCulling,
+    },
+};
+use bevy_egui::{
+    egui::{self, Pos2, Ui},
+    EguiContexts,
+};
+
+use crate::terrain::{color_ui, HydrologyImage, TERRAIN_SIZE_F32};
+
+pub use self::water_uniform::WaterUniform;
+
+pub struct WaterPlugin;
+
+impl Plugin for WaterPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(MaterialPlugin::<WaterMaterial>::default())
+            .init_resource::<WaterSettings>()
+            .add_systems(
+                Update,
+                (
+                    setup_water.run_if(resource_added::<HydrologyImage>),
+                    water_ui_system,
+                    update_water,
+                )
+                    .chain(),
+            );
+    }
+}
+
+/// Water surface seen through the terrain heightmap below it. The sea is a plane at sea level,
+/// inland water follows the terrain and only rises above it where the discharge map is high.
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+pub struct WaterMaterial {
+    #[uniform(0)]
+    pub uniform: WaterUniform,
+
+    #[texture(1, visibility(vertex, fragment))]
+    #[sampler(2, visibility(vertex, fragment))]
+    pub heightmap: Handle<Image>,
+
+    #[texture(3, visibility(vertex))]
+    #[sampler(4, visibility(vertex))]
+    pub discharge: Handle<Image>,
+}
+
+// Allowed on a module around the uniform only, see `terrain_uniform` in `terrain/uniforms.rs`.
+#[allow(dead_code)]
+mod water_uniform {
+    use bevy::{prelude::*, render::render_resource::ShaderType};
+
+    #[derive(ShaderType, Debug, Clone, Copy)]
+    pub struct WaterUniform {
+        pub shallow_color: Vec4,
+        pub deep_color: Vec4,
+        /// Color reflected at grazing angles.
+        pub reflection_color: Vec4,
+        /// How quickly the shallow color turns into the deep color with depth.
+        pub absorption: f32,
+        pub wave_strength: f32,
+        pub wave_frequency: f32,
+        pub wave_speed: f32,
+        /// Whether the surface follows the terrain, see [`super::WaterMaterial`].
+        pub inland: u32,
+        pub inland_threshold: f32,
+        pub inland_depth: f32,
+    }
+}
+
+impl Material for WaterMaterial {
+    fn vertex_shader() -> ShaderRef {
+        "shaders/water_material.wgsl".into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        "shaders/water_material.wgsl".into()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode {
+        AlphaMode::Blend
+    }
+}
+
+/// Water parameters edited in the UI, copied to the [`WaterMaterial`] when they change.
+#[derive(Resource, Clone, PartialEq)]
+pub struct WaterSettings {
+    pub visible: bool,
+    /// Height of the water plane.
+    pub level: f32,
+    pub shallow_color: LinearRgba,
+    pub deep_color: LinearRgba,
+    pub reflection_color: LinearRgba,
+    pub absorption: f32,
+    pub wave_strength: f32,
+    pub wave_frequency: f32,
+    pub wave_speed: f32,
+    /// Draw rivers and lakes where the discharge map exceeds `inland_threshold`.
+    pub show_inland: bool,
+    pub inland_threshold: f32,
+    /// Depth of inland water where the discharge is well above the threshold.
+    pub inland_depth: f32,
+}
+
+impl Default for WaterSettings {
+    fn default() -> Self {
+        Self {
+            visible: true,
+            level: 5.0,
+            shallow_color: LinearRgba::new(0.1, 0.4, 0.45, 0.4),
+            deep_color: LinearRgba::new(0.01, 0.05, 0.15, 0.95),
+            reflection_color: LinearRgba::new(0.35, 0.5, 0.66, 1.0),
+            absorption: 0.15,
+            wave_strength: 0.08,
+            wave_frequency: 0.6,
+            wave_speed: 1.5,
+            show_inland: true,
+            inland_threshold: 0.5,
+            inland_depth: 0.4,
+        }
+    }
+}
+
+impl WaterSettings {
+    fn uniform(&self, inland: bool) -> WaterUniform {
+        WaterUniform {
+            shallow_color: self.shallow_color.to_vec4(),
+            deep_color: self.deep_color.to_vec4(),
+            reflection_color: self.reflection_color.to_vec4(),
+            absorption: self.absorption,
+            wave_strength: self.wave_strength,
+            wave_frequency: self.wave_frequency,
+            wave_speed: self.wave_speed,
+            inland: inland.into(),
+            inland_threshold: self.inland_threshold,
+            inland_depth: self.inland_depth,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct WaterSurface;
+
+/// Marks the [`WaterSurface`] of rivers and lakes, as opposed to the sea.
+#[derive(Component)]
+pub struct InlandWater;
+
+/// Inland water has a vertex per cell, so it can follow the terrain.
+const INLAND_SUBDIVISIONS: u32 = 255;
+
+/// Spawns the water plane once the terrain heightmap exists.
+fn setup_water(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<WaterMaterial>>,
+    hydrology_image: Res<HydrologyImage>,
+    settings: Res<WaterSettings>,
+) {
+    commands.spawn((
+        WaterSurface,
+        Mesh3d(
+            meshes.add(
+                Plane3d::default()
+                    .mesh()
+                    .size(TERRAIN_SIZE_F32.x, TERRAIN_SIZE_F32.y),
+            ),
+        ),
+        MeshMaterial3d(materials.add(WaterMaterial {
+            uniform: settings.uniform(false),
+            heightmap: hydrology_image.heightmap.clone(),
+            discharge: hydrology_image.discharge.clone(),
+        })),
+        Transform::from_xyz(0.0, settings.level, 0.0),
+        NotShadowCaster,
+    ));
+
+    commands.spawn((
+        WaterSurface,
+        InlandWater,
+        Mesh3d(
+            meshes.add(
+                Plane3d::default()
+                    .mesh()
+                    .size(TERRAIN_SIZE_F32.x, TERRAIN_SIZE_F32.y)
+                    .subdivisions(INLAND_SUBDIVISIONS),
+            ),
+        ),
+        MeshMaterial3d(materials.add(WaterMaterial {
+            uniform: settings.uniform(true),
+            heightmap: hydrology_image.heightmap.clone(),
+            discharge: hydrology_image.discharge.clone(),
+        })),
+        Transform::default(),
+        if settings.visible && settings.show_inland {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        },
+        // The mesh is displaced in the vertex shader, outside of its flat bounds.
+        NoFrustumCulling,
+        NotShadowCaster,
+    ));
+}
+
+fn update_water(
+    settings: Res<WaterSettings>,
+    mut materials: ResMut<Assets<WaterMaterial>>,
+    mut water: Query<(
+        &mut Transform,
+        &mut Visibility,
+        &MeshMaterial3d<WaterMaterial>,
+        Has<InlandWater>,
+    )>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+
+    for (mut transform, mut visibility, material, inland) in &mut water {
+        let visible = if inland {
+            settings.visible && settings.show_inland
+        } else {
+            transform.translation.y = settings.level;
+            settings.visible
+        };
+        *visibility = if visible {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+        if let Some(material) = materials.get_mut(material) {
+            material.uniform = settings.uniform(inland);
+        }
+    }
+}
+
+pub fn water_ui(settings: &mut WaterSettings, ui: &mut Ui) {
+    ui.checkbox(&mut settings.visible, "Show water");
+    ui.end_row();
+    ui.add(egui::Slider::new(&mut settings.level, -20.0..=60.0).text("Water level"));
+    ui.end_row();
+    color_ui("Shallow color", &mut settings.shallow_color, ui);
+    color_ui("Deep color", &mut settings.deep_color, ui);
+    color_ui("Reflection color", &mut settings.reflection_color, ui);
+    ui.add(egui::Slider::new(&mut settings.absorption, 0.0..=1.0).text("Absorption"));
+    ui.end_row();
+    ui.add(egui::Slider::new(&mut settings.wave_strength, 0.0..=0.5).text("Wave strength"));
+    ui.end_row();
+    ui.add(egui::Slider::new(&mut settings.wave_frequency, 0.05..=4.0).text("Wave frequency"));
+    ui.end_row();
+    ui.add(egui::Slider::new(&mut settings.wave_speed, 0.0..=5.0).text("Wave speed"));
+    ui.end_row();
+    ui.checkbox(&mut settings.show_inland, "Show rivers and lakes");
+    ui.end_row();
+    ui.add_enabled(
+        settings.show_inland,
+        egui::Slider::new(&mut settings.inland_threshold, 0.01..=5.0)
+            .logarithmic(true)
+            .text("Discharge threshold"),
+    );
+    ui.end_row();
+    ui.add_enabled(
+        settings.show_inland,
+        egui::Slider::new(&mut settings.inland_depth, 0.05..=2.0).text("Inland depth"),
+    );
+    ui.end_row();
+}
+
+fn water_ui_system(mut settings: ResMut<WaterSettings>, mut contexts: EguiContexts) {
+    egui::Window::new("Water")
+        .current_pos(Pos2 { x: 1300., y: 230. })
+        .show(contexts.ctx_mut(), |ui| {
+            // Edited on a copy, so the material is only updated when something changed.
+            let mut edited = settings.clone();
+            egui::Grid::new("water_grid")
+                .num_columns(2)
+                .spacing([40.0, 4.0])
+                .striped(true)
+                .show(ui, |ui| {
+                    water_ui(&mut edited, ui);
+                });
+            settings.set_if_neq(edited);
+        });
+}